@@ -0,0 +1,71 @@
+use anyhow::{anyhow, Result};
+
+use crate::adapter::docker::{parse_expose, resolve_path, resolve_workdir, DockerfileAdapter};
+use crate::adapter::{BuildInfo, CopyFile, ImageInfo};
+use crate::config::cmd::BuildCmdArgs;
+use crate::util::expand::escape_vars;
+
+/// 'cmd:' 来源，通过命令行参数构建镜像，效果和对应的Dockerfile相同
+pub struct CmdAdapter {}
+
+impl CmdAdapter {
+    pub fn parse(build_cmds: &BuildCmdArgs) -> Result<(ImageInfo, BuildInfo)> {
+        let from = build_cmds.from.as_ref().ok_or_else(|| anyhow!("'cmd:' source must set the base image with '--from'"))?;
+        let (image_info, _) = DockerfileAdapter::parse_from_str(&format!("FROM {}", from))?;
+        // 命令行中的值不展开变量
+        let workdir = build_cmds.workdir.as_deref().map(|workdir| resolve_workdir(None, &escape_vars(workdir)));
+        // 和Dockerfile中先设置WORKDIR再COPY一样，相对的目标路径基于WORKDIR
+        let copy_files = build_cmds
+            .copy
+            .iter()
+            .map(|copy| CopyFile {
+                source_path: vec![escape_vars(&copy.source)],
+                dest_path: resolve_path(workdir.as_deref(), &escape_vars(&copy.dest)),
+                extract_archive: false,
+                checksum: None,
+                chown: None,
+                chmod: None,
+                from: None,
+            })
+            .collect();
+        let ports = build_cmds.expose.iter().map(|expose| parse_expose(expose)).collect::<Result<Vec<String>>>()?;
+        let build_info = BuildInfo {
+            labels: build_cmds.label.iter().map(|label| (escape_vars(&label.key), escape_vars(&label.value))).collect(),
+            envs: build_cmds.env.iter().map(|env| (env.key.clone(), escape_vars(&env.value))).collect(),
+            user: build_cmds.user.as_deref().map(escape_vars),
+            workdir,
+            cmd: Some(build_cmds.cmd.clone()).filter(|cmd| !cmd.is_empty()),
+            entrypoint: Some(build_cmds.entrypoint.clone()).filter(|entrypoint| !entrypoint.is_empty()),
+            copy_files,
+            ports: Some(ports).filter(|ports| !ports.is_empty()),
+            ..Default::default()
+        };
+        Ok((image_info, build_info))
+    }
+}
+
+#[test]
+fn cmd_source_works() -> Result<()> {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct TestCmd {
+        #[clap(flatten)]
+        build_cmds: BuildCmdArgs,
+    }
+    let args = "ocipack --source cmd: --target registry:example.com/app:1.0 --from alpine:3.18 --workdir /app/ \
+                --copy target/app:bin/ --env MODE=prod --entrypoint /app/bin/app --cmd serve --cmd --port=80 --expose 80";
+    let test_cmd = TestCmd::try_parse_from(args.split_whitespace())?;
+    let (image_info, build_info) = CmdAdapter::parse(&test_cmd.build_cmds)?;
+    assert_eq!(image_info.image_name, "alpine");
+    assert_eq!(image_info.reference, "3.18");
+    assert_eq!(build_info.workdir, Some("/app".to_string()));
+    assert_eq!(build_info.copy_files[0].source_path, ["target/app"]);
+    assert_eq!(build_info.copy_files[0].dest_path, "/app/bin/");
+    assert_eq!(build_info.envs.get("MODE"), Some(&"prod".to_string()));
+    assert_eq!(build_info.entrypoint, Some(vec!["/app/bin/app".to_string()]));
+    assert_eq!(build_info.cmd, Some(vec!["serve".to_string(), "--port=80".to_string()]));
+    assert_eq!(build_info.ports, Some(vec!["80/tcp".to_string()]));
+    assert!(TestCmd::try_parse_from(["ocipack", "--source", "cmd:alpine", "--target", "registry:example.com/app:1.0"]).is_err());
+    Ok(())
+}
@@ -1,20 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
-use dockerfile_parser::{BreakableStringComponent, Dockerfile, Instruction, ShellOrExecExpr};
-use log::{debug, warn};
+use dockerfile_parser::{BreakableString, BreakableStringComponent, Dockerfile, ImageRef, Instruction, ShellOrExecExpr};
+use log::{debug, info, warn};
 
-use crate::adapter::{BuildInfo, CopyFile, ImageInfo};
+use crate::adapter::{BuildInfo, CopyFile, CopyFrom, ImageInfo, StageInfo};
 use crate::const_data::DEFAULT_IMAGE_HOST;
+use crate::container::http::remote::is_remote_url;
+use crate::container::image::HealthConfig;
+use crate::util::expand::{escape_vars, expand_vars, expand_vars_partial};
+use crate::util::file::container_abs_path;
 
 pub struct DockerfileAdapter {}
 
 impl DockerfileAdapter {
-    pub fn parse(path: &str) -> Result<(ImageInfo, BuildInfo)> {
+    pub fn parse(path: &str, build_args: &HashMap<String, String>, target_stage: Option<&str>) -> Result<(ImageInfo, BuildInfo)> {
         if !Path::new(path).exists() {
             return Err(anyhow!("Dockerfile not found:{}", path));
         }
@@ -22,131 +26,734 @@ impl DockerfileAdapter {
         let mut str_body = String::new();
         let read_size = dockerfile_file.read_to_string(&mut str_body)?;
         debug!("Dockerfile size: {:?}", read_size);
-        Self::parse_from_str(&str_body)
+        Self::parse_with_args(&str_body, build_args, target_stage)
     }
 
     pub fn parse_from_str(str_body: &str) -> Result<(ImageInfo, BuildInfo)> {
+        Self::parse_with_args(str_body, &HashMap::new(), None)
+    }
+
+    /// 解析Dockerfile，build_args 会覆盖同名 ARG 的默认值。
+    /// target_stage 为输出镜像的阶段名称，没有指定时使用最后一个阶段
+    pub fn parse_with_args(
+        str_body: &str,
+        build_args: &HashMap<String, String>,
+        target_stage: Option<&str>,
+    ) -> Result<(ImageInfo, BuildInfo)> {
         let dockerfile = Dockerfile::parse(str_body)?;
-        let stages = dockerfile.stages().stages;
-        if stages.len() != 1 {
-            return Err(anyhow!("only support one stage in Dockerfile"));
-        }
-        let mut label_map = HashMap::<String, String>::new();
-        let mut from_image = None;
-        let mut user = None;
-        let mut workdir = None;
-        let mut envs_map = HashMap::<String, String>::new();
-        let mut cmd = None;
-        let mut copy_files = Vec::new();
-        let mut ports: Vec<String> = Vec::new();
+        let mut stages = Vec::<StageInfo>::new();
+        let mut current_stage: Option<StageBuilder> = None;
+        // FROM之前的ARG只在FROM中可用，FROM之后需要重新声明才能使用
+        let mut global_args = HashMap::<String, String>::new();
+        let mut used_build_args = HashSet::<String>::new();
         for instruction in dockerfile.instructions {
-            match instruction {
-                Instruction::From(from) => {
-                    from_image = Some(ImageInfo {
-                        image_raw_name: Some(from.image.content),
-                        image_host: from.image_parsed.registry.unwrap_or_else(|| DEFAULT_IMAGE_HOST.to_string()),
-                        image_name: from.image_parsed.image,
-                        reference: from
-                            .image_parsed
-                            .tag
-                            .or(from.image_parsed.hash)
-                            .or_else(|| Some(String::from("latest")))
-                            .ok_or_else(|| anyhow!("can not found hash or tag"))?,
-                    })
+            let (instruction, stage) = match (instruction, &mut current_stage) {
+                (Instruction::From(from), _) => {
+                    let image = expand_vars(&from.image.content, &|name: &str| global_args.get(name).cloned())?;
+                    if let Some(stage) = current_stage.take() {
+                        stages.push(stage.build());
+                    }
+                    if find_stage(&stages, &image).is_some() {
+                        return Err(anyhow!("FROM a previous stage is not supported: {}", image));
+                    }
+                    current_stage = Some(StageBuilder::new(from.alias.map(|alias| alias.content), parse_image_info(&image)?));
+                    continue;
                 }
-                Instruction::Arg(_) | Instruction::Run(_) => {
-                    warn!("un support ARG and RUN")
+                (Instruction::Arg(arg), stage) => {
+                    let name = arg.name.content;
+                    let default_value = match (arg.value, &stage) {
+                        (Some(value), Some(stage)) => Some(stage.expand(&value.content)?),
+                        (Some(value), None) => Some(expand_vars(&value.content, &|name: &str| global_args.get(name).cloned())?),
+                        // 在FROM之后重新声明的全局ARG继承全局的值
+                        (None, Some(_)) => global_args.get(&name).map(|value| escape_vars(value)),
+                        (None, None) => None,
+                    };
+                    // 阶段中的ARG和ENV一样保存为保留变量引用的格式，普通的值需要转义
+                    let value = if let Some(build_arg) = build_args.get(&name) {
+                        used_build_args.insert(name.clone());
+                        Some(if stage.is_some() {
+                            escape_vars(build_arg)
+                        } else {
+                            build_arg.clone()
+                        })
+                    } else {
+                        default_value
+                    };
+                    let args = match stage {
+                        Some(stage) => &mut stage.args,
+                        None => &mut global_args,
+                    };
+                    if let Some(value) = value {
+                        args.insert(name, value);
+                    }
+                    continue;
                 }
+                (instruction, Some(stage)) => (instruction, stage),
+                (_, None) => return Err(anyhow!("dockerfile must start with a 'From'")),
+            };
+            match instruction {
+                Instruction::From(_) | Instruction::Arg(_) => unreachable!(),
+                Instruction::Run(_) => warn!("un support RUN"),
                 Instruction::Label(label_i) => {
                     for label in label_i.labels {
-                        let _ = label_map.insert(label.name.content, label.value.content);
+                        let name = stage.expand(&label.name.content)?;
+                        let value = stage.expand(&label.value.content)?;
+                        let _ = stage.labels.insert(name, value);
                     }
                 }
-                Instruction::Entrypoint(entrypoint) => match &entrypoint.expr {
-                    ShellOrExecExpr::Shell(_shell) => {}
-                    ShellOrExecExpr::Exec(_exec) => {}
-                },
-                Instruction::Cmd(cmd_i) => {
-                    cmd = Some(match cmd_i.expr {
-                        ShellOrExecExpr::Shell(shell) => {
-                            let mut shells = shell
-                                .components
-                                .into_iter()
-                                .map(|component| match component {
-                                    BreakableStringComponent::String(str) => str.content,
-                                    BreakableStringComponent::Comment(comment) => comment.content,
-                                })
-                                .collect::<Vec<String>>();
-                            shells.insert(0, "/bin/sh".to_string());
-                            shells.insert(1, "-c".to_string());
-                            shells
-                        }
-                        ShellOrExecExpr::Exec(exec) => exec.elements.into_iter().map(|str| str.content).collect::<Vec<String>>(),
-                    })
-                }
+                Instruction::Entrypoint(entrypoint_i) => stage.entrypoint = Some(shell_or_exec_to_vec(entrypoint_i.expr, &stage.shell)),
+                Instruction::Cmd(cmd_i) => stage.cmd = Some(shell_or_exec_to_vec(cmd_i.expr, &stage.shell)),
                 Instruction::Copy(copy) => {
-                    if !copy.flags.is_empty() {
-                        return Err(anyhow!("copy not support flag"));
+                    let mut chown = None;
+                    let mut chmod = None;
+                    let mut from = None;
+                    for flag in copy.flags {
+                        let value = stage.expand(&flag.value.content)?;
+                        match flag.name.content.as_str() {
+                            "chown" => chown = Some(value),
+                            "chmod" => chmod = Some(parse_chmod(&expand_unresolved(&value)?)?),
+                            "from" => from = Some(copy_from(&stages, stage, expand_unresolved(&value)?)?),
+                            _ => return Err(anyhow!("copy not support flag: {}", flag.name.content)),
+                        }
+                    }
+                    let copy_file = CopyFile {
+                        source_path: copy.sources.iter().map(|str| stage.expand(&str.content)).collect::<Result<Vec<String>>>()?,
+                        dest_path: stage.dest_path(&stage.expand(&copy.destination.content)?),
+                        extract_archive: false,
+                        checksum: None,
+                        chown,
+                        chmod,
+                        from,
                     };
-                    copy_files.push(CopyFile {
-                        source_path: copy.sources.into_iter().map(|str| str.content).collect::<Vec<String>>(),
-                        dest_path: copy.destination.content,
-                    });
+                    stage.copy_files.push(copy_file);
                 }
                 Instruction::Env(env_i) => {
+                    // 同一条ENV中的变量只能引用之前已经存在的变量
+                    let mut new_envs = Vec::with_capacity(env_i.vars.len());
                     for mut env in env_i.vars {
-                        envs_map.insert(
-                            env.key.content,
-                            match env.value.components.remove(0) {
-                                BreakableStringComponent::String(string) => string.content,
-                                BreakableStringComponent::Comment(comment) => comment.content,
-                            },
-                        );
+                        let value = match env.value.components.remove(0) {
+                            BreakableStringComponent::String(string) => string.content,
+                            BreakableStringComponent::Comment(comment) => comment.content,
+                        };
+                        new_envs.push((env.key.content, stage.expand(&value)?));
                     }
+                    stage.envs.extend(new_envs);
                 }
                 Instruction::Misc(mut misc) => match misc.instruction.content.as_str() {
                     "USER" => {
                         if let BreakableStringComponent::String(str) = misc.arguments.components.remove(0) {
-                            user = Some(str.content.trim().to_string())
+                            stage.user = Some(stage.expand(str.content.trim())?)
                         }
                     }
                     "WORKDIR" => {
                         if let BreakableStringComponent::String(str) = misc.arguments.components.remove(0) {
-                            workdir = Some(str.content.trim().to_string());
+                            let workdir = stage.expand(str.content.trim())?;
+                            stage.workdir = Some(resolve_workdir(stage.workdir.as_deref(), &workdir));
                         }
                     }
                     "EXPOSE" => {
                         if let BreakableStringComponent::String(ports_str) = misc.arguments.components.remove(0) {
-                            for str in ports_str.content.split_whitespace() {
-                                let expose = if str.ends_with("/tcp") || str.ends_with("/udp") {
-                                    let _port_num = u16::from_str(&str[..str.len() - 4])?;
-                                    str.to_string()
-                                } else {
-                                    format!("{}/tcp", u16::from_str(str)?)
-                                };
-                                ports.push(expose)
+                            let ports_str = stage.expand(&ports_str.content)?;
+                            for str in ports_str.split_whitespace() {
+                                // 引用了基础镜像变量的端口在构建时展开后再解析
+                                stage.ports.push(if str.contains('$') { str.to_string() } else { parse_expose(str)? })
+                            }
+                        }
+                    }
+                    "VOLUME" => {
+                        let volumes_str = breakable_to_string(misc.arguments);
+                        if volumes_str.starts_with('[') {
+                            let json_volumes = serde_json::from_str::<Vec<String>>(&volumes_str)
+                                .map_err(|err| anyhow!("error json array '{}': {}", volumes_str, err))?;
+                            for volume in json_volumes {
+                                stage.volumes.push(stage.expand(&volume)?);
                             }
+                        } else {
+                            let volumes_str = stage.expand(&volumes_str)?;
+                            stage.volumes.extend(volumes_str.split_whitespace().map(|str| str.to_string()));
+                        }
+                    }
+                    "ADD" => {
+                        let add_arguments = stage.expand(&breakable_to_string(misc.arguments))?;
+                        let AddArguments { mut flags, mut paths } = parse_add_arguments(&add_arguments)?;
+                        let checksum = flags.remove("checksum");
+                        let chown = flags.remove("chown");
+                        let chmod = flags.remove("chmod").map(|chmod| parse_chmod(&expand_unresolved(&chmod)?)).transpose()?;
+                        if let Some(flag) = flags.keys().next() {
+                            return Err(anyhow!("add not support flag: {}", flag));
+                        }
+                        if paths.len() < 2 {
+                            return Err(anyhow!("ADD requires at least one source and a destination"));
+                        }
+                        let dest_path = paths.pop().expect("ADD destination");
+                        if checksum.is_some() && !paths.iter().all(|path| is_remote_url(path)) {
+                            return Err(anyhow!("checksum can't be specified for non-HTTP(S) sources"));
+                        }
+                        stage.copy_files.push(CopyFile {
+                            source_path: paths,
+                            dest_path: stage.dest_path(&dest_path),
+                            extract_archive: true,
+                            checksum,
+                            chown,
+                            chmod,
+                            from: None,
+                        });
+                    }
+                    "HEALTHCHECK" => stage.healthcheck = Some(parse_healthcheck(&breakable_to_string(misc.arguments))?),
+                    "STOPSIGNAL" => stage.stop_signal = Some(stage.expand(&breakable_to_string(misc.arguments))?),
+                    "SHELL" => {
+                        let shell_str = breakable_to_string(misc.arguments);
+                        let shell = serde_json::from_str::<Vec<String>>(&shell_str)
+                            .map_err(|err| anyhow!("SHELL requires the arguments to be in JSON form '{}': {}", shell_str, err))?;
+                        if shell.is_empty() {
+                            return Err(anyhow!("SHELL requires at least one argument"));
+                        }
+                        stage.shell = Some(shell);
+                    }
+                    "ONBUILD" => {
+                        let trigger = breakable_to_string(misc.arguments);
+                        let keyword = trigger.split_whitespace().next().unwrap_or_default().to_ascii_uppercase();
+                        match keyword.as_str() {
+                            "" => return Err(anyhow!("ONBUILD requires an instruction")),
+                            "ONBUILD" | "FROM" | "MAINTAINER" => return Err(anyhow!("{} isn't allowed as an ONBUILD trigger", keyword)),
+                            _ => stage.on_build.push(trigger),
                         }
                     }
-                    "VOLUME" => warn!("un support VOLUME"),
-                    "ADD" => warn!("TODO , need support ADD"),
                     "MAINTAINER" => warn!("un support MAINTAINER"),
                     _ => warn!("unknown dockerfile field:{}", misc.instruction.content),
                 },
             }
         }
-        let image_info = from_image.ok_or_else(|| anyhow!("dockerfile must has a 'From'"))?;
-        Ok((
+        for unused_arg in build_args.keys().filter(|key| !used_build_args.contains(*key)) {
+            warn!("build arg '{}' was not consumed by any ARG", unused_arg);
+        }
+        stages.push(current_stage.ok_or_else(|| anyhow!("dockerfile must has a 'From'"))?.build());
+        let target_index = match target_stage {
+            Some(target_stage) => find_stage(&stages, target_stage).ok_or_else(|| anyhow!("target stage not found: {}", target_stage))?,
+            None => stages.len() - 1,
+        };
+        // 目标阶段之后的阶段不需要构建
+        stages.truncate(target_index + 1);
+        let target = stages.pop().expect("target stage");
+        let mut build_info = target.build_info;
+        build_info.stages = stages;
+        Ok((target.image_info, build_info))
+    }
+}
+
+impl DockerfileAdapter {
+    /// 在Dockerfile自身的指令之前执行基础镜像中的ONBUILD触发器，触发器中的变量只能引用触发器中的ARG和ENV
+    pub fn apply_on_build(build_info: BuildInfo, triggers: &[String]) -> Result<BuildInfo> {
+        if triggers.is_empty() {
+            return Ok(build_info);
+        }
+        info!("Execute {} ONBUILD trigger(s) of the base image", triggers.len());
+        let (_, trigger_info) = Self::parse_from_str(&format!("FROM scratch\n{}\n", triggers.join("\n")))?;
+        let trigger_workdir = trigger_info.workdir.as_deref();
+        let mut labels = trigger_info.labels;
+        labels.extend(build_info.labels);
+        let mut envs = trigger_info.envs;
+        envs.extend(build_info.envs);
+        let mut copy_files = trigger_info.copy_files;
+        // Dockerfile中的相对路径基于触发器设置的WORKDIR
+        copy_files.extend(build_info.copy_files.into_iter().map(|mut copy_file| {
+            copy_file.dest_path = resolve_path(trigger_workdir, &copy_file.dest_path);
+            copy_file
+        }));
+        let workdir = match build_info.workdir {
+            Some(workdir) => Some(resolve_workdir(trigger_workdir, &workdir)),
+            None => trigger_info.workdir.clone(),
+        };
+        // ENTRYPOINT会重置之前的CMD
+        let (entrypoint, cmd) = match build_info.entrypoint {
+            Some(entrypoint) => (Some(entrypoint), build_info.cmd),
+            None => (trigger_info.entrypoint, build_info.cmd.or(trigger_info.cmd)),
+        };
+        Ok(BuildInfo {
+            labels,
+            envs,
+            user: build_info.user.or(trigger_info.user),
+            workdir,
+            cmd,
+            entrypoint,
+            copy_files,
+            ports: concat_option(trigger_info.ports, build_info.ports),
+            volumes: concat_option(trigger_info.volumes, build_info.volumes),
+            healthcheck: build_info.healthcheck.or(trigger_info.healthcheck),
+            stop_signal: build_info.stop_signal.or(trigger_info.stop_signal),
+            shell: build_info.shell.or(trigger_info.shell),
+            on_build: build_info.on_build,
+            stages: build_info.stages,
+        })
+    }
+}
+
+fn concat_option(first: Option<Vec<String>>, second: Option<Vec<String>>) -> Option<Vec<String>> {
+    match (first, second) {
+        (Some(mut first), Some(second)) => {
+            first.extend(second);
+            Some(first)
+        }
+        (first, second) => first.or(second),
+    }
+}
+
+/// 解析中的构建阶段
+struct StageBuilder {
+    name: Option<String>,
+    image_info: ImageInfo,
+    args: HashMap<String, String>,
+    labels: HashMap<String, String>,
+    envs: HashMap<String, String>,
+    user: Option<String>,
+    workdir: Option<String>,
+    cmd: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    copy_files: Vec<CopyFile>,
+    ports: Vec<String>,
+    volumes: Vec<String>,
+    healthcheck: Option<HealthConfig>,
+    stop_signal: Option<String>,
+    shell: Option<Vec<String>>,
+    on_build: Vec<String>,
+}
+
+impl StageBuilder {
+    fn new(name: Option<String>, image_info: ImageInfo) -> StageBuilder {
+        StageBuilder {
+            name,
             image_info,
-            BuildInfo {
-                labels: label_map,
-                envs: envs_map,
-                user,
-                workdir,
-                cmd,
-                copy_files,
-                ports: if ports.is_empty() { None } else { Some(ports) },
+            args: HashMap::new(),
+            labels: HashMap::new(),
+            envs: HashMap::new(),
+            user: None,
+            workdir: None,
+            cmd: None,
+            entrypoint: None,
+            copy_files: Vec::new(),
+            ports: Vec::new(),
+            volumes: Vec::new(),
+            healthcheck: None,
+            stop_signal: None,
+            shell: None,
+            on_build: Vec::new(),
+        }
+    }
+
+    /// 展开变量，ENV的优先级高于ARG。
+    /// 解析时没有基础镜像的Env，其他变量保留到构建时展开，例如 'ENV PATH=/app/bin:$PATH'、'WORKDIR $HOME'
+    fn expand(&self, input: &str) -> Result<String> {
+        expand_vars_partial(input, &|name: &str| self.envs.get(name).or_else(|| self.args.get(name)).cloned())
+    }
+
+    /// 相对路径基于当前的WORKDIR，没有WORKDIR时保留相对路径，在构建时基于基础镜像的WORKDIR
+    fn dest_path(&self, path: &str) -> String {
+        resolve_path(self.workdir.as_deref(), path)
+    }
+
+    fn build(self) -> StageInfo {
+        StageInfo {
+            name: self.name,
+            image_info: self.image_info,
+            build_info: BuildInfo {
+                labels: self.labels,
+                envs: self.envs,
+                user: self.user,
+                workdir: self.workdir,
+                cmd: self.cmd,
+                entrypoint: self.entrypoint,
+                copy_files: self.copy_files,
+                ports: if self.ports.is_empty() { None } else { Some(self.ports) },
+                volumes: if self.volumes.is_empty() { None } else { Some(self.volumes) },
+                healthcheck: self.healthcheck,
+                stop_signal: self.stop_signal,
+                shell: self.shell,
+                on_build: self.on_build,
+                stages: Vec::new(),
             },
-        ))
+        }
+    }
+}
+
+/// 基于WORKDIR解析路径，没有绝对路径的WORKDIR时保留相对路径，在构建时基于基础镜像的WORKDIR解析
+pub fn resolve_path(workdir: Option<&str>, path: &str) -> String {
+    match workdir {
+        // 以基础镜像的变量开头时无法确定是否为绝对路径，保留到构建时展开，这类变量通常是绝对路径，例如 '$HOME/app'
+        _ if path.starts_with('$') => path.to_string(),
+        Some(workdir) if workdir.starts_with('/') => container_abs_path(workdir, path),
+        Some(workdir) if !path.starts_with('/') => format!("{}/{}", workdir.trim_end_matches('/'), path),
+        _ if path.starts_with('/') => container_abs_path("/", path),
+        _ => path.to_string(),
     }
 }
+
+/// 基于当前的WORKDIR解析新的WORKDIR，去掉末尾的 '/'
+pub fn resolve_workdir(current_workdir: Option<&str>, workdir: &str) -> String {
+    let workdir = resolve_path(current_workdir, workdir);
+    if workdir.len() > 1 {
+        workdir.trim_end_matches('/').to_string()
+    } else {
+        workdir
+    }
+}
+
+/// 解析EXPOSE的端口，没有协议时默认为tcp
+pub fn parse_expose(str: &str) -> Result<String> {
+    Ok(if str.ends_with("/tcp") || str.ends_with("/udp") {
+        let _port_num = u16::from_str(&str[..str.len() - 4])?;
+        str.to_string()
+    } else {
+        format!("{}/tcp", u16::from_str(str)?)
+    })
+}
+
+fn parse_image_info(image: &str) -> Result<ImageInfo> {
+    let image_parsed = ImageRef::parse(image);
+    Ok(ImageInfo {
+        image_raw_name: Some(image.to_string()),
+        image_host: image_parsed.registry.unwrap_or_else(|| DEFAULT_IMAGE_HOST.to_string()),
+        image_name: image_parsed.image,
+        reference: image_parsed
+            .tag
+            .or(image_parsed.hash)
+            .or_else(|| Some(String::from("latest")))
+            .ok_or_else(|| anyhow!("can not found hash or tag"))?,
+    })
+}
+
+/// 按名称查找阶段，名称不区分大小写
+fn find_stage(stages: &[StageInfo], name: &str) -> Option<usize> {
+    stages.iter().position(|stage| stage.name.as_ref().is_some_and(|stage_name| stage_name.eq_ignore_ascii_case(name)))
+}
+
+/// COPY --from 的值为之前阶段的名称或者序号时从阶段中复制，否则从镜像中复制
+fn copy_from(stages: &[StageInfo], current_stage: &StageBuilder, value: String) -> Result<CopyFrom> {
+    if let Some(index) = find_stage(stages, &value) {
+        return Ok(CopyFrom::Stage(index));
+    }
+    if let Ok(index) = value.parse::<usize>() {
+        return if index < stages.len() {
+            Ok(CopyFrom::Stage(index))
+        } else {
+            Err(anyhow!("COPY --from can only refer to a previous stage: {}", value))
+        };
+    }
+    if current_stage.name.as_ref().is_some_and(|name| name.eq_ignore_ascii_case(&value)) {
+        return Err(anyhow!("COPY --from can not refer to the current stage: {}", value));
+    }
+    Ok(CopyFrom::Image(value))
+}
+
+/// 展开解析时就需要确定的值中剩余的变量，例如 --chmod 和 --from，基础镜像中的变量在这里不可用
+fn expand_unresolved(value: &str) -> Result<String> {
+    expand_vars(value, &|name: &str| {
+        warn!("variable '{}' is not available here, expanded as empty", name);
+        None
+    })
+}
+
+/// 将shell或exec格式的CMD/ENTRYPOINT转换为数组，shell格式使用SHELL指令设置的shell包装，默认为 '/bin/sh -c'
+fn shell_or_exec_to_vec(expr: ShellOrExecExpr, shell: &Option<Vec<String>>) -> Vec<String> {
+    match expr {
+        ShellOrExecExpr::Shell(command) => {
+            let mut cmd = shell.clone().unwrap_or_else(|| vec!["/bin/sh".to_string(), "-c".to_string()]);
+            cmd.push(breakable_to_string(command));
+            cmd
+        }
+        ShellOrExecExpr::Exec(exec) => exec.elements.into_iter().map(|str| str.content).collect::<Vec<String>>(),
+    }
+}
+
+/// ADD指令的参数，paths的最后一个为目标路径
+struct AddArguments {
+    flags: HashMap<String, String>,
+    paths: Vec<String>,
+}
+
+/// 解析ADD的参数，支持JSON数组格式
+fn parse_add_arguments(arguments: &str) -> Result<AddArguments> {
+    let (flags, rest) = split_flags(arguments)?;
+    let paths = if rest.starts_with('[') {
+        serde_json::from_str::<Vec<String>>(rest).map_err(|err| anyhow!("error json array '{}': {}", rest, err))?
+    } else {
+        rest.split_whitespace().map(|str| str.to_string()).collect()
+    };
+    Ok(AddArguments { flags, paths })
+}
+
+/// 拆分指令开头 '--name=value' 格式的参数，返回参数和剩余的内容
+fn split_flags(arguments: &str) -> Result<(HashMap<String, String>, &str)> {
+    let mut flags = HashMap::new();
+    let mut rest = arguments.trim();
+    while rest.starts_with("--") {
+        let (flag, remain) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let (name, value) = flag[2..].split_once('=').ok_or_else(|| anyhow!("error flag:{}", flag))?;
+        flags.insert(name.to_string(), value.to_string());
+        rest = remain.trim_start();
+    }
+    Ok((flags, rest))
+}
+
+/// 解析HEALTHCHECK，'NONE' 会禁用基础镜像中的健康检查
+fn parse_healthcheck(arguments: &str) -> Result<HealthConfig> {
+    let (mut flags, rest) = split_flags(arguments)?;
+    if rest.eq_ignore_ascii_case("NONE") {
+        if !flags.is_empty() {
+            return Err(anyhow!("HEALTHCHECK NONE does not accept flags"));
+        }
+        return Ok(HealthConfig {
+            test: Some(vec!["NONE".to_string()]),
+            ..Default::default()
+        });
+    }
+    let (keyword, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if !keyword.eq_ignore_ascii_case("CMD") {
+        return Err(anyhow!("HEALTHCHECK must be 'NONE' or 'CMD': {}", rest));
+    }
+    let command = command.trim();
+    if command.is_empty() {
+        return Err(anyhow!("HEALTHCHECK CMD requires a command"));
+    }
+    // 不是合法JSON数组时和docker一样按shell格式处理
+    let test = match serde_json::from_str::<Vec<String>>(command) {
+        Ok(exec) => std::iter::once("CMD".to_string()).chain(exec).collect(),
+        Err(_) => vec!["CMD-SHELL".to_string(), command.to_string()],
+    };
+    let health_config = HealthConfig {
+        test: Some(test),
+        interval: flags.remove("interval").map(|interval| parse_duration(&interval)).transpose()?,
+        timeout: flags.remove("timeout").map(|timeout| parse_duration(&timeout)).transpose()?,
+        start_period: flags.remove("start-period").map(|start_period| parse_duration(&start_period)).transpose()?,
+        retries: flags
+            .remove("retries")
+            .map(|retries| retries.parse::<u64>().map_err(|_| anyhow!("error retries: {}", retries)))
+            .transpose()?,
+    };
+    if let Some(flag) = flags.keys().next() {
+        return Err(anyhow!("healthcheck not support flag: {}", flag));
+    }
+    Ok(health_config)
+}
+
+/// 解析Go格式的时长，例如 '30s'、'1m30s'、'500ms'，返回纳秒
+fn parse_duration(duration: &str) -> Result<u64> {
+    if duration == "0" {
+        return Ok(0);
+    }
+    let error = || anyhow!("error duration: {}", duration);
+    let mut rest = duration;
+    let mut nanos = 0f64;
+    if rest.is_empty() {
+        return Err(error());
+    }
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number = rest[..number_len].parse::<f64>().map_err(|_| error())?;
+        rest = &rest[number_len..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ns" => 1f64,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            _ => return Err(error()),
+        };
+        nanos += number * unit;
+        rest = &rest[unit_len..];
+    }
+    Ok(nanos as u64)
+}
+
+/// 解析 --chmod 的八进制权限，例如 '0755'
+fn parse_chmod(chmod: &str) -> Result<u32> {
+    match u32::from_str_radix(chmod, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(anyhow!("error chmod: {}", chmod)),
+    }
+}
+
+/// 拼接多行的字符串，忽略其中的注释
+fn breakable_to_string(breakable: BreakableString) -> String {
+    breakable
+        .components
+        .into_iter()
+        .filter_map(|component| match component {
+            BreakableStringComponent::String(str) => Some(str.content),
+            BreakableStringComponent::Comment(_) => None,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn entrypoint_works() -> Result<()> {
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nENTRYPOINT [\"/app\", \"run\"]\n")?;
+    assert_eq!(build_info.entrypoint, Some(vec!["/app".to_string(), "run".to_string()]));
+    assert_eq!(build_info.cmd, None);
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nENTRYPOINT exec /app \\\n  --debug\nCMD [\"-v\"]\n")?;
+    assert_eq!(build_info.entrypoint.unwrap()[..2], ["/bin/sh", "-c"]);
+    assert_eq!(build_info.cmd, Some(vec!["-v".to_string()]));
+    Ok(())
+}
+
+#[test]
+fn add_works() -> Result<()> {
+    let dockerfile = "FROM ubuntu\nADD vendor.tar.gz /opt/\nADD [\"a b.txt\", \"c.txt\", \"/data/\"]\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    assert_eq!(build_info.copy_files.len(), 2);
+    assert!(build_info.copy_files.iter().all(|copy_file| copy_file.extract_archive));
+    assert_eq!(build_info.copy_files[0].source_path, vec!["vendor.tar.gz".to_string()]);
+    assert_eq!(
+        build_info.copy_files[1].source_path,
+        vec!["a b.txt".to_string(), "c.txt".to_string()]
+    );
+    assert_eq!(build_info.copy_files[1].dest_path, "/data/");
+    Ok(())
+}
+
+#[test]
+fn volume_works() -> Result<()> {
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nVOLUME /data /logs\nVOLUME [\"/var/lib/app\"]\n")?;
+    assert_eq!(
+        build_info.volumes,
+        Some(vec!["/data".to_string(), "/logs".to_string(), "/var/lib/app".to_string()])
+    );
+    Ok(())
+}
+
+#[test]
+fn arg_works() -> Result<()> {
+    let dockerfile = r#"ARG BASE=ubuntu
+ARG TAG=20.04
+FROM ${BASE}:${TAG}
+ARG TAG
+ARG APP_DIR=/opt/app
+ENV HOME_DIR=${APP_DIR}/home
+LABEL version=$TAG
+WORKDIR $HOME_DIR
+COPY app-${TAG}.jar ${APP_DIR:-/srv}/
+"#;
+    let build_args = HashMap::from([("TAG".to_string(), "22.04".to_string())]);
+    let (image_info, build_info) = DockerfileAdapter::parse_with_args(dockerfile, &build_args, None)?;
+    assert_eq!(image_info.image_name, "ubuntu");
+    assert_eq!(image_info.reference, "22.04");
+    assert_eq!(build_info.envs.get("HOME_DIR").map(String::as_str), Some("/opt/app/home"));
+    assert_eq!(build_info.labels.get("version").map(String::as_str), Some("22.04"));
+    assert_eq!(build_info.workdir.as_deref(), Some("/opt/app/home"));
+    assert_eq!(build_info.copy_files[0].source_path, vec!["app-22.04.jar".to_string()]);
+    assert_eq!(build_info.copy_files[0].dest_path, "/opt/app/");
+    // 全局ARG在FROM之后未重新声明时不可用，变量保留到构建时使用基础镜像的Env展开
+    let (_, build_info) = DockerfileAdapter::parse_from_str("ARG NAME=app\nFROM ubuntu\nWORKDIR /$NAME\n")?;
+    assert_eq!(build_info.workdir.as_deref(), Some("/$NAME"));
+    let build_info = build_info.expand_base_envs(&HashMap::new())?;
+    assert_eq!(build_info.workdir.as_deref(), Some("/"));
+    Ok(())
+}
+
+#[test]
+fn copy_flags_works() -> Result<()> {
+    let dockerfile = "FROM ubuntu\nCOPY --chown=app:app --chmod=0755 run.sh /app/\nADD --chown=1000 conf.tar /etc/\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    assert_eq!(build_info.copy_files[0].chown.as_deref(), Some("app:app"));
+    assert_eq!(build_info.copy_files[0].chmod, Some(0o755));
+    assert_eq!(build_info.copy_files[1].chown.as_deref(), Some("1000"));
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nCOPY --from=golang:1.20 /usr/local/go /opt/go\n")?;
+    assert_eq!(build_info.copy_files[0].from, Some(CopyFrom::Image("golang:1.20".to_string())));
+    assert!(DockerfileAdapter::parse_from_str("FROM ubuntu\nCOPY --chmod=999 run.sh /app/\n").is_err());
+    assert!(DockerfileAdapter::parse_from_str("FROM ubuntu\nCOPY --link=true run.sh /app/\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn multi_stage_works() -> Result<()> {
+    let dockerfile = r#"FROM golang:1.20 AS builder
+COPY main.go /src/
+FROM alpine AS assets
+COPY static /static
+FROM ubuntu
+COPY --from=Builder /src/app /usr/bin/app
+COPY --from=1 /static /var/www
+COPY --from=nginx:latest /etc/nginx /etc/nginx
+"#;
+    let (image_info, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    assert_eq!(image_info.image_name, "ubuntu");
+    assert_eq!(build_info.stages.len(), 2);
+    assert_eq!(build_info.stages[0].image_info.image_name, "golang");
+    let froms = build_info.copy_files.iter().map(|copy_file| copy_file.from.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        froms,
+        vec![
+            Some(CopyFrom::Stage(0)),
+            Some(CopyFrom::Stage(1)),
+            Some(CopyFrom::Image("nginx:latest".to_string()))
+        ]
+    );
+    let (image_info, build_info) = DockerfileAdapter::parse_with_args(dockerfile, &HashMap::new(), Some("assets"))?;
+    assert_eq!(image_info.image_name, "alpine");
+    assert_eq!(build_info.stages.len(), 1);
+    assert!(DockerfileAdapter::parse_from_str("FROM alpine AS base\nFROM base\n").is_err());
+    assert!(DockerfileAdapter::parse_from_str("FROM alpine\nCOPY --from=0 /a /b\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn copy_dest_works() -> Result<()> {
+    let dockerfile =
+        "FROM ubuntu\nCOPY a.txt conf/\nWORKDIR /app\nWORKDIR lib\nCOPY b.txt .\nCOPY c.txt ../c.conf\nADD d.tar /opt/./data/\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    let dest_paths = build_info.copy_files.iter().map(|copy_file| copy_file.dest_path.as_str()).collect::<Vec<&str>>();
+    assert_eq!(dest_paths, vec!["conf/", "/app/lib/", "/app/c.conf", "/opt/data/"]);
+    assert_eq!(build_info.workdir.as_deref(), Some("/app/lib"));
+    Ok(())
+}
+
+#[test]
+fn healthcheck_works() -> Result<()> {
+    let dockerfile = "FROM ubuntu\nHEALTHCHECK --interval=1m30s --retries=3 CMD curl -f http://localhost/ || exit 1\nSTOPSIGNAL SIGKILL\n\
+                      SHELL [\"/bin/bash\", \"-c\"]\nCMD echo hello\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    let healthcheck = build_info.healthcheck.unwrap();
+    assert_eq!(healthcheck.test.unwrap(), ["CMD-SHELL", "curl -f http://localhost/ || exit 1"]);
+    assert_eq!(healthcheck.interval, Some(90_000_000_000));
+    assert_eq!(healthcheck.timeout, None);
+    assert_eq!(healthcheck.retries, Some(3));
+    assert_eq!(build_info.stop_signal, Some("SIGKILL".to_string()));
+    assert_eq!(build_info.shell, Some(vec!["/bin/bash".to_string(), "-c".to_string()]));
+    assert_eq!(
+        build_info.cmd,
+        Some(vec!["/bin/bash".to_string(), "-c".to_string(), "echo hello".to_string()])
+    );
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nHEALTHCHECK --timeout=500ms CMD [\"/check\", \"-q\"]\n")?;
+    let healthcheck = build_info.healthcheck.unwrap();
+    assert_eq!(healthcheck.test.unwrap(), ["CMD", "/check", "-q"]);
+    assert_eq!(healthcheck.timeout, Some(500_000_000));
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nHEALTHCHECK NONE\n")?;
+    assert_eq!(build_info.healthcheck.unwrap().test.unwrap(), ["NONE"]);
+    assert!(DockerfileAdapter::parse_from_str("FROM ubuntu\nHEALTHCHECK --interval=5x CMD true\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn on_build_works() -> Result<()> {
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM ubuntu\nONBUILD COPY . /app/src\nONBUILD ENV MODE=prod\n")?;
+    assert_eq!(build_info.on_build, ["COPY . /app/src", "ENV MODE=prod"]);
+    assert!(DockerfileAdapter::parse_from_str("FROM ubuntu\nONBUILD FROM alpine\n").is_err());
+    let triggers = [
+        "WORKDIR /app".to_string(),
+        "COPY a.txt ./".to_string(),
+        "ENV MODE=prod".to_string(),
+        "CMD [\"run\"]".to_string(),
+    ];
+    let (_, build_info) = DockerfileAdapter::parse_from_str("FROM base\nCOPY b.txt lib/\nENV MODE=dev\nENTRYPOINT [\"/app/start\"]\n")?;
+    let build_info = DockerfileAdapter::apply_on_build(build_info, &triggers)?;
+    let dest_paths = build_info.copy_files.iter().map(|copy_file| copy_file.dest_path.as_str()).collect::<Vec<&str>>();
+    assert_eq!(dest_paths, ["/app/", "/app/lib/"]);
+    assert_eq!(build_info.envs.get("MODE"), Some(&"dev".to_string()));
+    assert_eq!(build_info.workdir, Some("/app".to_string()));
+    assert_eq!(build_info.entrypoint, Some(vec!["/app/start".to_string()]));
+    assert_eq!(build_info.cmd, None);
+    Ok(())
+}
@@ -1,12 +1,22 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
+use anyhow::Result;
+
+use crate::adapter::docker::{parse_expose, resolve_path, resolve_workdir};
 use crate::config::cmd::TargetFormat;
+use crate::const_data::DEFAULT_IMAGE_HOST;
+use crate::container::image::HealthConfig;
 use crate::container::Platform;
+use crate::util::expand::expand_vars;
 
+pub mod cmd;
 pub mod docker;
+pub mod oci_dir;
 pub mod registry;
 pub mod tar;
 
+#[derive(Clone)]
 pub struct ImageInfo {
     pub image_raw_name: Option<String>,
     pub image_host: String,
@@ -14,19 +24,136 @@ pub struct ImageInfo {
     pub reference: String,
 }
 
+impl ImageInfo {
+    /// FROM scratch 表示空的基础镜像
+    pub fn is_scratch(&self) -> bool {
+        self.image_raw_name.as_deref() == Some("scratch")
+    }
+}
+
 pub struct SourceInfo {
     pub image_info: ImageInfo,
     pub platform: Option<Platform>,
+    /// 本地的源镜像，为None时从镜像仓库拉取
+    pub local: Option<LocalSource>,
+}
+
+impl SourceInfo {
+    /// 从镜像仓库拉取的源镜像，本地镜像和 scratch 为None
+    pub fn registry_image(&self) -> Option<&ImageInfo> {
+        match self.local.is_none() && !self.image_info.is_scratch() {
+            true => Some(&self.image_info),
+            false => None,
+        }
+    }
+}
+
+/// 本地的源镜像，不需要访问镜像仓库
+#[derive(Clone)]
+pub enum LocalSource {
+    /// docker save 生成的tar文件
+    DockerArchive(PathBuf),
+    /// OCI image layout目录，tag对应index.json中的 'org.opencontainers.image.ref.name'
+    OciLayout { path: PathBuf, tag: Option<String> },
+}
+
+impl LocalSource {
+    /// 本地镜像没有镜像名称，使用文件名作为名称
+    pub fn image_info(&self) -> ImageInfo {
+        let (path, tag) = match self {
+            LocalSource::DockerArchive(path) => (path, None),
+            LocalSource::OciLayout { path, tag } => (path, tag.clone()),
+        };
+        let image_name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .filter(|stem| !stem.is_empty())
+            .unwrap_or_else(|| String::from("image"));
+        let reference = tag.unwrap_or_else(|| String::from("latest"));
+        ImageInfo {
+            image_raw_name: Some(format!("{}:{}", image_name, reference)),
+            image_host: DEFAULT_IMAGE_HOST.to_string(),
+            image_name,
+            reference,
+        }
+    }
 }
 
+#[derive(Clone, Default)]
 pub struct BuildInfo {
+    /// 解析时保留了未定义的变量引用，构建时使用 expand_base_envs 展开
     pub labels: HashMap<String, String>,
     pub envs: HashMap<String, String>,
     pub user: Option<String>,
     pub workdir: Option<String>,
     pub cmd: Option<Vec<String>>,
+    pub entrypoint: Option<Vec<String>>,
     pub copy_files: Vec<CopyFile>,
     pub ports: Option<Vec<String>>,
+    pub volumes: Option<Vec<String>>,
+    pub healthcheck: Option<HealthConfig>,
+    pub stop_signal: Option<String>,
+    /// SHELL指令设置的默认shell
+    pub shell: Option<Vec<String>>,
+    /// ONBUILD触发器，写入到镜像配置中
+    pub on_build: Vec<String>,
+    /// 目标阶段之前的所有阶段，用于 COPY --from=<stage>
+    pub stages: Vec<StageInfo>,
+}
+
+impl BuildInfo {
+    /// 使用基础镜像的Env展开labels、envs、user、workdir、ports、volumes、stop_signal和复制文件的路径中保留的变量引用，
+    /// 和Docker一样，基础镜像中的变量在所有指令中都可用
+    pub fn expand_base_envs(self, base_envs: &HashMap<String, String>) -> Result<BuildInfo> {
+        let expand = |value: &str| expand_vars(value, &|name: &str| base_envs.get(name).cloned());
+        let labels = self.labels.iter().map(|(name, value)| Ok((expand(name)?, expand(value)?))).collect::<Result<_>>()?;
+        let envs = self.envs.iter().map(|(key, value)| Ok((key.clone(), expand(value)?))).collect::<Result<_>>()?;
+        let copy_files = self
+            .copy_files
+            .into_iter()
+            .map(|copy_file| {
+                Ok(CopyFile {
+                    source_path: copy_file.source_path.iter().map(|path| expand(path)).collect::<Result<_>>()?,
+                    dest_path: resolve_path(None, &expand(&copy_file.dest_path)?),
+                    checksum: copy_file.checksum.as_deref().map(expand).transpose()?,
+                    chown: copy_file.chown.as_deref().map(expand).transpose()?,
+                    ..copy_file
+                })
+            })
+            .collect::<Result<_>>()?;
+        let ports = match self.ports {
+            Some(ports) => {
+                let mut expanded_ports = Vec::with_capacity(ports.len());
+                for port in ports {
+                    for str in expand(&port)?.split_whitespace() {
+                        expanded_ports.push(parse_expose(str)?);
+                    }
+                }
+                Some(expanded_ports)
+            }
+            None => None,
+        };
+        let volumes = self.volumes.map(|volumes| volumes.iter().map(|volume| expand(volume)).collect::<Result<_>>()).transpose()?;
+        Ok(BuildInfo {
+            labels,
+            envs,
+            user: self.user.as_deref().map(expand).transpose()?,
+            workdir: self.workdir.as_deref().map(expand).transpose()?.map(|workdir| resolve_workdir(None, &workdir)),
+            copy_files,
+            ports,
+            volumes,
+            stop_signal: self.stop_signal.as_deref().map(expand).transpose()?,
+            ..self
+        })
+    }
+}
+
+/// Dockerfile中的一个构建阶段
+#[derive(Clone)]
+pub struct StageInfo {
+    pub name: Option<String>,
+    pub image_info: ImageInfo,
+    pub build_info: BuildInfo,
 }
 
 pub struct TargetInfo {
@@ -44,7 +171,25 @@ pub trait TargetImageAdapter {
     fn info(&self) -> &TargetInfo;
 }
 
+#[derive(Clone)]
 pub struct CopyFile {
     pub source_path: Vec<String>,
     pub dest_path: String,
+    /// ADD指令，本地的tar归档会被解压到目标目录
+    pub extract_archive: bool,
+    /// ADD --checksum，只用于远程文件
+    pub checksum: Option<String>,
+    /// --chown=user:group，名称在构建时从基础镜像中解析
+    pub chown: Option<String>,
+    /// --chmod，覆盖所有写入文件的权限
+    pub chmod: Option<u32>,
+    /// COPY --from，从其他镜像或者之前的阶段中复制文件
+    pub from: Option<CopyFrom>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CopyFrom {
+    Image(String),
+    /// 之前阶段的序号
+    Stage(usize),
 }
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{create_dir_all, read_to_string, File};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::info;
+use serde_json::Map;
+
+use crate::container::home::HomeDir;
+use crate::container::image::oci::{OciDescriptor, OciLayoutIndex, REF_NAME_ANNOTATION};
+use crate::container::manifest::Manifest;
+use crate::container::{ConfigBlobSerialize, RegContentType, RegDigest};
+use crate::util::file::write_atomic;
+use crate::util::sha::bytes_sha256;
+
+pub const OCI_LAYOUT_CONTENT: &str = r#"{"imageLayoutVersion":"1.0.0"}"#;
+
+/// 写入OCI image layout目录，目录已经存在时追加镜像，相同digest的blob只保存一份。
+/// layout中只能保存OCI格式的manifest，Docker格式的manifest会被转换
+pub struct OciDirTargetAdapter {
+    pub tag: Option<String>,
+    pub target_manifest: Manifest,
+    pub target_config_blob_serialize: ConfigBlobSerialize,
+    pub save_path: PathBuf,
+}
+
+impl OciDirTargetAdapter {
+    pub fn save(self, home_dir: &HomeDir) -> Result<()> {
+        info!("Start writing image to OCI layout: {:?}", self.save_path);
+        let target_manifest = Manifest::OciV1(self.target_manifest.to_oci_v1(&self.target_config_blob_serialize)?);
+        let blobs_dir = self.save_path.join("blobs").join("sha256");
+        create_dir_all(&blobs_dir)?;
+        let layout_path = self.save_path.join("oci-layout");
+        if !layout_path.exists() {
+            fs::write(layout_path, OCI_LAYOUT_CONTENT)?;
+        }
+        for layer in target_manifest.layers() {
+            let digest = RegDigest::new_with_digest(layer.digest.to_string());
+            let blob_path = blobs_dir.join(&digest.sha256);
+            if blob_path.exists() {
+                continue;
+            }
+            let local_layer =
+                home_dir.cache.blobs.local_layer(&digest).ok_or_else(|| anyhow!("can not found this layer: {}", digest.digest))?;
+            write_atomic(&blob_path, |file| {
+                io::copy(&mut File::open(&local_layer.layer_file_path)?, file)?;
+                Ok(())
+            })?;
+        }
+        let config_blob = self.target_config_blob_serialize;
+        write_blob(&blobs_dir, &config_blob.digest.sha256, config_blob.json_str.as_bytes())?;
+        let manifest_json = target_manifest.to_json_string()?;
+        let manifest_digest = RegDigest::new_with_sha256(bytes_sha256(manifest_json.as_bytes()));
+        write_blob(&blobs_dir, &manifest_digest.sha256, manifest_json.as_bytes())?;
+
+        let index_path = self.save_path.join("index.json");
+        let mut index = if index_path.exists() {
+            serde_json::from_str::<OciLayoutIndex>(&read_to_string(&index_path)?)?
+        } else {
+            OciLayoutIndex {
+                schema_version: 2,
+                media_type: Some(RegContentType::OCI_INDEX.val().to_string()),
+                manifests: Vec::new(),
+                extra: Map::new(),
+            }
+        };
+        let descriptor = OciDescriptor {
+            media_type: target_manifest.media_type().to_string(),
+            size: manifest_json.len() as u64,
+            digest: manifest_digest.digest,
+            platform: None,
+            annotations: self.tag.map(|tag| HashMap::from([(REF_NAME_ANNOTATION.to_string(), tag)])),
+            extra: Map::new(),
+        };
+        // 同一个tag只能指向一个镜像，没有tag时不重复添加相同的镜像
+        index.manifests.retain(|item| match descriptor.ref_name() {
+            Some(tag) => item.ref_name() != Some(tag),
+            None => item.digest != descriptor.digest || item.annotations.is_some(),
+        });
+        index.manifests.push(descriptor);
+        let index_json = serde_json::to_string(&index)?;
+        write_atomic(&index_path, |file| Ok(file.write_all(index_json.as_bytes())?))?;
+        Ok(())
+    }
+}
+
+/// blob以sha256命名，已经存在时不需要重复写入
+fn write_blob(blobs_dir: &Path, sha256: &str, data: &[u8]) -> Result<()> {
+    let blob_path = blobs_dir.join(sha256);
+    if blob_path.exists() {
+        return Ok(());
+    }
+    write_atomic(&blob_path, |file| Ok(file.write_all(data)?))
+}
+
+#[test]
+fn oci_dir_keeps_unknown_fields_works() -> Result<()> {
+    use crate::subcmd::pull::scratch;
+
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(&temp_dir.path().join("home"))?;
+    let save_path = temp_dir.path().join("layout");
+    create_dir_all(&save_path)?;
+    // 其他工具写入的index.json，包含没有用到的字段
+    let index_json = r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[{"mediaType":"application/vnd.oci.image.manifest.v1+json","size":10,"digest":"sha256:0000000000000000000000000000000000000000000000000000000000000000","artifactType":"application/vnd.example+type","urls":["https://example.com/blob"],"data":"e30=","annotations":{"org.opencontainers.image.ref.name":"sbom"}}],"annotations":{"org.example.owner":"team"},"subject":{"mediaType":"application/vnd.oci.image.manifest.v1+json","size":1,"digest":"sha256:1111111111111111111111111111111111111111111111111111111111111111"}}"#;
+    fs::write(save_path.join("index.json"), index_json)?;
+    let pull_result = scratch(None)?;
+    let adapter = OciDirTargetAdapter {
+        tag: Some("1.0".to_string()),
+        target_manifest: pull_result.manifest,
+        target_config_blob_serialize: pull_result.config_blob.serialize()?,
+        save_path: save_path.clone(),
+    };
+    adapter.save(&home_dir)?;
+    let index = serde_json::from_str::<serde_json::Value>(&read_to_string(save_path.join("index.json"))?)?;
+    let original = serde_json::from_str::<serde_json::Value>(index_json)?;
+    assert_eq!(index["annotations"], original["annotations"]);
+    assert_eq!(index["subject"], original["subject"]);
+    assert_eq!(index["manifests"][0], original["manifests"][0]);
+    assert_eq!(index["manifests"][1]["annotations"][REF_NAME_ANNOTATION], "1.0");
+    Ok(())
+}
+
+#[test]
+fn oci_dir_works() -> Result<()> {
+    use crate::container::CompressType;
+    use crate::subcmd::pull::scratch;
+
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(&temp_dir.path().join("home"))?;
+    let layer_data = b"compressed layer";
+    let layer_sha256 = bytes_sha256(layer_data);
+    let layer_file = temp_dir.path().join("layer");
+    fs::write(&layer_file, layer_data)?;
+    home_dir.cache.blobs.move_to_blob(&layer_file, &layer_sha256, "diff")?;
+    home_dir.cache.blobs.create_layer_config("diff", &layer_sha256, CompressType::Tgz)?;
+    let pull_result = scratch(None)?;
+    let mut manifest = pull_result.manifest;
+    manifest.add_top_layer(layer_data.len() as u64, layer_sha256.clone(), CompressType::Tgz)?;
+    let save_path = temp_dir.path().join("layout");
+    // --format docker 时也保存为OCI格式的manifest
+    let docker_manifest = Manifest::DockerV2S2(manifest.clone().to_docker_v2_s2(&pull_result.config_blob.serialize()?)?);
+    for (tag, target_manifest) in [("1.0", &manifest), ("2.0", &docker_manifest), ("1.0", &docker_manifest)] {
+        let adapter = OciDirTargetAdapter {
+            tag: Some(tag.to_string()),
+            target_manifest: target_manifest.clone(),
+            target_config_blob_serialize: pull_result.config_blob.serialize()?,
+            save_path: save_path.clone(),
+        };
+        adapter.save(&home_dir)?;
+    }
+    assert_eq!(fs::read_to_string(save_path.join("oci-layout"))?, OCI_LAYOUT_CONTENT);
+    assert_eq!(fs::read(save_path.join("blobs/sha256").join(&layer_sha256))?, layer_data);
+    let index = serde_json::from_str::<OciLayoutIndex>(&read_to_string(save_path.join("index.json"))?)?;
+    let tags = index.manifests.iter().filter_map(|descriptor| descriptor.ref_name()).collect::<Vec<&str>>();
+    assert_eq!(tags, ["2.0", "1.0"]);
+    let manifest_digest = RegDigest::new_with_digest(index.manifests[0].digest.clone());
+    let manifest_json = read_to_string(save_path.join("blobs/sha256").join(&manifest_digest.sha256))?;
+    assert_eq!(manifest_json, manifest.to_json_string()?);
+    assert!(index.manifests.iter().all(|descriptor| descriptor.media_type == RegContentType::OCI_MANIFEST.val()));
+    assert_eq!(fs::read_dir(save_path.join("blobs/sha256"))?.count(), 3);
+    Ok(())
+}
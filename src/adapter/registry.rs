@@ -5,7 +5,7 @@ use log::{debug, info};
 use crate::adapter::{ImageInfo, TargetImageAdapter, TargetInfo};
 use crate::config::cmd::{BaseAuth, TargetFormat};
 use crate::config::RegAuthType;
-use crate::const_data::DEFAULT_IMAGE_HOST;
+use crate::const_data::{DEFAULT_IMAGE_HOST, DOCKER_IO_HOST};
 use crate::container::http::upload::UploadResult;
 use crate::container::manifest::Manifest;
 use crate::container::proxy::ProxyInfo;
@@ -15,6 +15,43 @@ use crate::progress::ProcessResult;
 use crate::progress::Processor;
 use crate::GLOBAL_CONFIG;
 
+/// 解析推送目标的镜像名称，没有host时为Docker Hub
+pub fn parse_target_image(image_raw: &str) -> Result<ImageInfo> {
+    let temp_from = format!("FROM {}", image_raw);
+    let instruction = Dockerfile::parse(&temp_from)?.instructions.remove(0);
+    match instruction {
+        Instruction::From(from) => Ok(ImageInfo {
+            image_raw_name: None,
+            image_host: from.image_parsed.registry.unwrap_or_else(|| DEFAULT_IMAGE_HOST.to_string()),
+            image_name: if from.image_parsed.image.contains('/') {
+                from.image_parsed.image
+            } else {
+                format!("library/{}", from.image_parsed.image)
+            },
+            reference: from.image_parsed.tag.or(from.image_parsed.hash).unwrap_or_else(|| "latest".to_string()),
+        }),
+        _ => Err(anyhow!("image info error")),
+    }
+}
+
+/// docker.io 和 Docker Hub 的仓库地址是同一个仓库
+pub fn registry_host(host: &str) -> &str {
+    match host {
+        DOCKER_IO_HOST => DEFAULT_IMAGE_HOST,
+        host => host,
+    }
+}
+
+/// 推送到一个镜像仓库目标时使用的连接参数
+pub struct RegistryConnection<'a> {
+    pub use_https: bool,
+    pub auth: Option<&'a BaseAuth>,
+    pub proxy: Option<ProxyInfo>,
+    pub conn_timeout_second: u64,
+    /// 分块上传blob时每一块的字节数
+    pub upload_chunk_size: u64,
+}
+
 pub struct RegistryTargetAdapter {
     info: TargetInfo,
     use_https: bool,
@@ -23,6 +60,9 @@ pub struct RegistryTargetAdapter {
     target_config_blob_serialize: ConfigBlobSerialize,
     target_auth: RegAuthType,
     target_proxy: Option<ProxyInfo>,
+    upload_chunk_size: u64,
+    /// 同一个仓库中的源镜像名称，推送时先尝试跨仓库挂载blob
+    mount_from: Option<String>,
 }
 
 impl TargetImageAdapter for RegistryTargetAdapter {
@@ -35,40 +75,33 @@ impl RegistryTargetAdapter {
     pub fn new(
         image_raw: &str,
         format: TargetFormat,
-        use_https: bool,
         target_manifest: Manifest,
         target_config_blob_serialize: ConfigBlobSerialize,
-        base_auth: Option<&BaseAuth>,
-        conn_timeout_second: u64,
-        target_proxy: Option<ProxyInfo>,
+        connection: RegistryConnection,
     ) -> Result<RegistryTargetAdapter> {
-        let temp_from = format!("FROM {}", image_raw);
-        let instruction = Dockerfile::parse(&temp_from)?.instructions.remove(0);
-        let image_info = match instruction {
-            Instruction::From(from) => ImageInfo {
-                image_raw_name: None,
-                image_host: from.image_parsed.registry.unwrap_or_else(|| DEFAULT_IMAGE_HOST.to_string()),
-                image_name: if from.image_parsed.image.contains('/') {
-                    from.image_parsed.image
-                } else {
-                    format!("library/{}", from.image_parsed.image)
-                },
-                reference: from.image_parsed.tag.or(from.image_parsed.hash).unwrap_or_else(|| "latest".to_string()),
-            },
-            _ => return Err(anyhow!("image info error")),
-        };
-        let auth = RegAuthType::build_auth(image_info.image_host.clone(), base_auth);
+        let image_info = parse_target_image(image_raw)?;
+        let auth = RegAuthType::build_auth(image_info.image_host.clone(), connection.auth);
         Ok(RegistryTargetAdapter {
             info: TargetInfo { image_info, format },
-            use_https,
-            conn_timeout_second,
+            use_https: connection.use_https,
+            conn_timeout_second: connection.conn_timeout_second,
             target_manifest,
             target_config_blob_serialize,
             target_auth: auth,
-            target_proxy,
+            target_proxy: connection.proxy,
+            upload_chunk_size: connection.upload_chunk_size,
+            mount_from: None,
         })
     }
 
+    /// 源镜像和目标在同一个仓库时，blob可以直接从源镜像挂载，不需要重新上传
+    pub fn mount_from(mut self, source: Option<&ImageInfo>) -> RegistryTargetAdapter {
+        self.mount_from = source
+            .filter(|source| registry_host(&source.image_host) == registry_host(&self.info.image_info.image_host))
+            .map(|source| source.image_name.clone());
+        self
+    }
+
     pub fn upload(self) -> Result<()> {
         let home_dir = GLOBAL_CONFIG.home_dir.clone();
         let target_info = self.info;
@@ -89,7 +122,13 @@ impl RegistryTargetAdapter {
             let local_layer =
                 home_dir.cache.blobs.local_layer(&layer_digest).ok_or_else(|| anyhow!("local file not found {}", layer_digest.digest))?;
             let layer_path = local_layer.layer_path();
-            let reg_uploader = manager.layer_blob_upload(&target_info.image_info.image_name, &layer_digest, &layer_path)?;
+            let reg_uploader = manager.layer_blob_upload(
+                &target_info.image_info.image_name,
+                &layer_digest,
+                &layer_path,
+                self.upload_chunk_size,
+                self.mount_from.as_deref(),
+            )?;
             reg_uploader_vec.push(Box::new(reg_uploader))
         }
         let serialize = self.target_config_blob_serialize;
@@ -97,8 +136,13 @@ impl RegistryTargetAdapter {
 
         let config_blob_path = home_dir.cache.write_temp_file(config_blob_str)?;
         let config_blob_path_str = config_blob_path.to_string_lossy().to_string();
-        let config_blob_uploader =
-            manager.layer_blob_upload(&target_info.image_info.image_name, &serialize.digest, &config_blob_path_str)?;
+        let config_blob_uploader = manager.layer_blob_upload(
+            &target_info.image_info.image_name,
+            &serialize.digest,
+            &config_blob_path_str,
+            self.upload_chunk_size,
+            self.mount_from.as_deref(),
+        )?;
         reg_uploader_vec.push(Box::new(config_blob_uploader));
         //
         let process_manager = ProcessorManager::new_processor_manager(reg_uploader_vec)?;
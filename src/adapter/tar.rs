@@ -1,27 +1,22 @@
+use std::collections::HashSet;
 use std::fs::{create_dir_all, File};
 use std::io;
-use std::io::{Cursor, Write};
+use std::io::{Cursor, Read, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use tar::{Builder, Header};
 
-use crate::container::manifest::{CommonManifestConfig, Manifest};
-use crate::container::{CompressType, ConfigBlobSerialize, RegContentType, RegDigest};
-use crate::util::sha::bytes_sha256;
-use crate::GLOBAL_CONFIG;
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ImageIndex {
-    pub schema_version: usize,
-    pub manifests: Vec<CommonManifestConfig>,
-}
+use crate::adapter::oci_dir::OciDirTargetAdapter;
+use crate::container::home::HomeDir;
+use crate::container::manifest::Manifest;
+use crate::container::{CompressType, ConfigBlobSerialize, RegDigest};
+use crate::util::file::write_atomic;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -31,114 +26,202 @@ pub struct TarManifestJson {
     pub layers: Vec<String>,
 }
 
-pub struct TarTargetAdapter {
-    pub image_raw_name: String,
+/// 保存为 docker save 格式的tar文件，可以直接使用 docker load 加载
+pub struct DockerArchiveTargetAdapter {
+    /// 写入 RepoTags 的镜像名称，没有设置时 docker load 后只有镜像ID
+    pub reference: Option<String>,
     pub target_manifest: Manifest,
-    pub manifest_raw: String,
     pub target_config_blob_serialize: ConfigBlobSerialize,
     pub save_path: PathBuf,
     pub use_gzip: bool,
 }
 
-impl TarTargetAdapter {
-    pub fn save(self) -> Result<()> {
-        info!("start saving image as file");
-        if self.save_path.exists() {
-            return Err(anyhow!("file already exists: {:?}", self.save_path));
+impl DockerArchiveTargetAdapter {
+    pub fn save(self, home_dir: &HomeDir) -> Result<()> {
+        info!("Start saving image as docker archive: {:?}", self.save_path);
+        prepare_output(&self.save_path)?;
+        if self.reference.is_none() {
+            warn!("No image name is set for the docker archive, the loaded image will be untagged.");
         }
-        if let Some(parent) = self.save_path.parent() {
-            create_dir_all(parent)?;
+        write_atomic(&self.save_path, |file| {
+            match self.use_gzip {
+                true => {
+                    self.write_entries(GzEncoder::new(file, Compression::fast()), home_dir)?.finish()?;
+                }
+                false => {
+                    self.write_entries(file, home_dir)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn write_entries<W: Write>(&self, writer: W, home_dir: &HomeDir) -> Result<W> {
+        let mut builder = Builder::new(writer);
+        // docker load 要求layer为未压缩的tar，顺序和config中的diff_ids相同
+        let mut written = HashSet::new();
+        let mut layer_paths = Vec::new();
+        for layer in self.target_manifest.layers() {
+            let digest = RegDigest::new_with_digest(layer.digest.to_string());
+            let local_layer =
+                home_dir.cache.blobs.local_layer(&digest).ok_or_else(|| anyhow!("can not found this layer: {}", digest.digest))?;
+            let layer_path = format!("{}/layer.tar", local_layer.diff_layer_sha);
+            if written.insert(layer_path.clone()) {
+                let layer_file = File::open(&local_layer.layer_file_path)?;
+                let mut layer_reader: Box<dyn Read> = match local_layer.compress_type {
+                    CompressType::Tar => Box::new(layer_file),
+                    CompressType::Tgz => Box::new(GzDecoder::new(layer_file)),
+                    CompressType::Zstd => Box::new(zstd::stream::Decoder::new(layer_file)?),
+                };
+                // tar的header中需要文件大小，先解压到临时文件
+                let mut temp_file = tempfile::Builder::new().tempfile_in(&home_dir.cache.temp_dir)?;
+                let size = io::copy(&mut layer_reader, &mut temp_file)?;
+                append_reader(&mut builder, &layer_path, size, temp_file.reopen()?)?;
+            }
+            layer_paths.push(layer_path);
         }
-        let output_file = File::create(self.save_path)?;
-        let write_box: Box<dyn Write> = match self.use_gzip {
-            true => Box::new(GzEncoder::new(output_file, Compression::fast())),
-            false => Box::new(output_file),
+        let config_blob = &self.target_config_blob_serialize;
+        let config_path = format!("{}.json", config_blob.digest.sha256);
+        append_bytes(&mut builder, &config_path, config_blob.json_str.as_bytes())?;
+        let manifest_json = TarManifestJson {
+            config: config_path,
+            repo_tags: self.reference.iter().cloned().collect(),
+            layers: layer_paths,
         };
-        let mut builder = Builder::new(write_box);
+        append_bytes(
+            &mut builder,
+            "manifest.json",
+            serde_json::to_string(&vec![manifest_json])?.as_bytes(),
+        )?;
+        Ok(builder.into_inner()?)
+    }
+}
 
-        let (manifest_media_type, layers) = match self.target_manifest {
-            Manifest::OciV1(oci) => (RegContentType::OCI_MANIFEST.0.to_string(), oci.layers.clone()),
-            Manifest::DockerV2S2(dockerv2s2) => (RegContentType::DOCKER_MANIFEST.0.to_string(), dockerv2s2.layers.clone()),
-        };
+/// 保存为打包成tar文件的OCI image layout，blobs中保存的是manifest引用的压缩layer
+pub struct OciArchiveTargetAdapter {
+    pub tag: Option<String>,
+    pub target_manifest: Manifest,
+    pub target_config_blob_serialize: ConfigBlobSerialize,
+    pub save_path: PathBuf,
+}
 
-        // 解析所有layer为tar
-        let layer_sha_vec: Vec<String> = layers
-            .iter()
-            .map(|comm_layer| {
-                let digest = RegDigest::new_with_digest(comm_layer.digest.clone());
-                let layer = GLOBAL_CONFIG
-                    .home_dir
-                    .cache
-                    .blobs
-                    .local_layer(&digest)
-                    .ok_or_else(|| anyhow!("can not found this layer: {}", digest.sha256))?;
-                let layer_file = File::open(layer.layer_file_path)?;
-                let (size, layer_reader) = match layer.compress_type {
-                    CompressType::Tar => (layer_file.metadata()?.len(), Box::new(layer_file)),
-                    CompressType::Tgz => {
-                        let mut temp_file = tempfile::Builder::new().tempfile_in(&GLOBAL_CONFIG.home_dir.cache.temp_dir)?;
-                        let size = io::copy(&mut GzDecoder::new(layer_file), &mut temp_file)?;
-                        (size, Box::new(temp_file.reopen()?))
-                    }
-                    CompressType::Zstd => {
-                        let mut temp_file = tempfile::Builder::new().tempfile_in(&GLOBAL_CONFIG.home_dir.cache.temp_dir)?;
-                        let size = io::copy(&mut zstd::stream::Decoder::new(layer_file)?, &mut temp_file)?;
-                        (size, Box::new(temp_file.reopen()?))
-                    }
-                };
-                let mut header = Header::new_gnu();
-                let layer_path = format!("blobs/sha256/{}", layer.diff_layer_sha);
-                header.set_path(&layer_path)?;
-                header.set_size(size);
-                header.set_mode(0o644);
-                header.set_cksum();
-                builder.append(&header, layer_reader)?;
-                Ok(layer_path)
-            })
-            .collect::<Result<Vec<_>>>()?;
-
-        // 将config blob 也加入到layer中
-        let config_blob = self.target_config_blob_serialize;
-        //layer_sha_vec.push(config_blob.digest.sha256.clone());
-        let config_blob_path = format!("blobs/sha256/{}", config_blob.digest.sha256);
-        write_string_to_builder(config_blob.json_str, config_blob_path.clone(), &mut builder)?;
-        // 写入 index.json
-
-        let manifest_digest = RegDigest::new_with_sha256(bytes_sha256(self.manifest_raw.as_bytes()));
-        let image_index = ImageIndex {
-            schema_version: 2,
-            manifests: vec![CommonManifestConfig {
-                media_type: manifest_media_type.clone(),
-                size: manifest_media_type.as_bytes().len() as u64,
-                digest: manifest_digest.digest,
-            }],
-        };
-        write_string_to_builder(serde_json::to_string(&image_index)?, "index.json", &mut builder)?;
-        // 写入 manifest.json
-        let manifest_json = TarManifestJson {
-            config: config_blob_path,
-            repo_tags: vec![self.image_raw_name.clone()],
-            layers: layer_sha_vec,
+impl OciArchiveTargetAdapter {
+    pub fn save(self, home_dir: &HomeDir) -> Result<()> {
+        info!("Start saving image as OCI archive: {:?}", self.save_path);
+        prepare_output(&self.save_path)?;
+        // 先在临时目录中写入完整的layout，再打包
+        let layout_dir = tempfile::Builder::new().tempdir_in(&home_dir.cache.temp_dir)?;
+        let oci_dir_adapter = OciDirTargetAdapter {
+            tag: self.tag,
+            target_manifest: self.target_manifest,
+            target_config_blob_serialize: self.target_config_blob_serialize,
+            save_path: layout_dir.path().to_path_buf(),
         };
-        write_string_to_builder(serde_json::to_string(&vec![manifest_json])?, "manifest.json", &mut builder)?;
-        // 写入 oci-layout
-        write_string_to_builder(r#"{"imageLayoutVersion":"1.0.0"}"#.to_string(), "oci-layout", &mut builder)?;
-        // 写入manifest
-        let manifest_path = format!("blobs/sha256/{}", manifest_digest.sha256);
-        write_string_to_builder(self.manifest_raw, manifest_path, &mut builder)?;
-        builder.finish()?;
-        Ok(())
+        oci_dir_adapter.save(home_dir)?;
+        write_atomic(&self.save_path, |file| {
+            let mut builder = Builder::new(file);
+            builder.append_path_with_name(layout_dir.path().join("oci-layout"), "oci-layout")?;
+            builder.append_path_with_name(layout_dir.path().join("index.json"), "index.json")?;
+            builder.append_dir_all("blobs", layout_dir.path().join("blobs"))?;
+            builder.finish()?;
+            Ok(())
+        })
     }
 }
 
-fn write_string_to_builder<P: AsRef<Path>>(data: String, path: P, builder: &mut Builder<Box<dyn Write>>) -> Result<()> {
-    let size = data.as_bytes().len() as u64;
-    let image_index_cursor = Cursor::new(data);
+fn prepare_output(save_path: &Path) -> Result<()> {
+    if save_path.exists() {
+        return Err(anyhow!("file already exists: {:?}", save_path));
+    }
+    if let Some(parent) = save_path.parent() {
+        create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+fn append_reader<W: Write, R: Read>(builder: &mut Builder<W>, path: &str, size: u64, reader: R) -> Result<()> {
     let mut header = Header::new_gnu();
     header.set_path(path)?;
     header.set_size(size);
     header.set_mode(0o644);
     header.set_cksum();
-    builder.append(&header, image_index_cursor)?;
+    builder.append(&header, reader)?;
+    Ok(())
+}
+
+fn append_bytes<W: Write>(builder: &mut Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    append_reader(builder, path, data.len() as u64, Cursor::new(data))
+}
+
+#[test]
+fn archive_target_works() -> Result<()> {
+    use std::fs;
+
+    use tar::Archive;
+
+    use crate::config::cmd::TargetFormat;
+    use crate::subcmd::build::build_target_manifest;
+    use crate::subcmd::load::{load_docker_archive, load_oci_layout};
+
+    fn append_entry<W: Write>(builder: &mut Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+        append_bytes(builder, path, data)
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(&temp_dir.path().join("home"))?;
+    let mut layer_builder = Builder::new(Vec::new());
+    append_entry(&mut layer_builder, "hello.txt", b"hello")?;
+    let layer = layer_builder.into_inner()?;
+    let config = format!(
+        r#"{{"architecture":"amd64","os":"linux","config":{{}},"rootfs":{{"type":"layers","diff_ids":["sha256:{}"]}},"history":[]}}"#,
+        crate::util::sha::bytes_sha256(&layer)
+    );
+    let source_path = temp_dir.path().join("source.tar");
+    let mut builder = Builder::new(File::create(&source_path)?);
+    append_entry(&mut builder, "layer/layer.tar", &layer)?;
+    append_entry(&mut builder, "config.json", config.as_bytes())?;
+    append_entry(
+        &mut builder,
+        "manifest.json",
+        br#"[{"Config":"config.json","RepoTags":[],"Layers":["layer/layer.tar"]}]"#,
+    )?;
+    builder.into_inner()?;
+    let source = load_docker_archive(&source_path, &home_dir)?;
+    let config_blob_serialize = source.config_blob.serialize()?;
+    // 默认的 --format docker
+    let target_manifest = build_target_manifest(source.manifest, &TargetFormat::Docker, None, &config_blob_serialize)?;
+
+    // docker-archive 使用未压缩的layer，RepoTags 为目标名称
+    let docker_path = temp_dir.path().join("out/docker.tgz");
+    DockerArchiveTargetAdapter {
+        reference: Some("demo/app:1.0".to_string()),
+        target_manifest: target_manifest.clone(),
+        target_config_blob_serialize: source.config_blob.serialize()?,
+        save_path: docker_path.clone(),
+        use_gzip: true,
+    }
+    .save(&home_dir)?;
+    let unpack_dir = temp_dir.path().join("docker");
+    Archive::new(GzDecoder::new(File::open(&docker_path)?)).unpack(&unpack_dir)?;
+    let manifest_json: Vec<TarManifestJson> = serde_json::from_str(&fs::read_to_string(unpack_dir.join("manifest.json"))?)?;
+    assert_eq!(manifest_json[0].repo_tags, vec!["demo/app:1.0".to_string()]);
+    assert_eq!(fs::read(unpack_dir.join(&manifest_json[0].layers[0]))?, layer);
+    let reloaded = load_docker_archive(&docker_path, &home_dir)?;
+    assert_eq!(reloaded.manifest.config_digest(), config_blob_serialize.digest.digest);
+
+    // oci-archive 解包后为可以读取的OCI image layout，manifest总是OCI格式
+    let oci_path = temp_dir.path().join("out/oci.tar");
+    OciArchiveTargetAdapter {
+        tag: Some("1.0".to_string()),
+        target_manifest: target_manifest.clone(),
+        target_config_blob_serialize: source.config_blob.serialize()?,
+        save_path: oci_path.clone(),
+    }
+    .save(&home_dir)?;
+    let layout_dir = temp_dir.path().join("layout");
+    Archive::new(File::open(&oci_path)?).unpack(&layout_dir)?;
+    let reloaded = load_oci_layout(&layout_dir, Some("1.0"), None, &home_dir)?;
+    assert!(matches!(reloaded.manifest, Manifest::OciV1(_)));
+    assert_eq!(reloaded.manifest.layers()[0].digest, target_manifest.layers()[0].digest);
+    assert_eq!(reloaded.manifest.config_digest(), config_blob_serialize.digest.digest);
     Ok(())
 }
@@ -1,4 +1,6 @@
 use std::env;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 use anyhow::Result;
@@ -80,8 +82,8 @@ pub struct BuildCmdArgs {
     pub target_allow_insecure: bool,
 
     /// Source type.
-    /// Support dockerfile/registry type
-    /// Example:'dockerfile:/path/to/.Dockerfile','registry:redis:latest'
+    /// Support dockerfile/registry/cmd/docker-archive/oci type, 'cmd:' builds with the '--from', '--copy'... options instead of a Dockerfile
+    /// Example:'dockerfile:/path/to/.Dockerfile','registry:redis:latest','cmd:','docker-archive:/path/image.tar','oci:/path/layout:1.0'
     #[clap(long, short)]
     pub source: SourceType,
 
@@ -93,11 +95,11 @@ pub struct BuildCmdArgs {
     #[clap(long)]
     pub source_proxy: Option<ProxyInfo>,
 
-    /// Target type.
-    /// Support registry/tar/tgz.
-    /// Example:'registry:my.container.com/target/image:1.1','tgz:image.tgz'
-    #[clap(long, short)]
-    pub target: TargetType,
+    /// Target type, can be used multiple times to push several tags or registries in one build.
+    /// Support registry/docker-archive/oci-archive/oci-dir, 'oci-dir' appends to an existing OCI image layout.
+    /// Example:'registry:my.container.com/target/image:1.1','docker-archive:image.tar:my/image:1.1','oci-archive:image.tar:1.1','oci-dir:/path/layout:1.1'
+    #[clap(long, short, required = true)]
+    pub target: Vec<TargetType>,
 
     /// [OPTION] Auth of push target image. Example:'myname:mypass','myname:${MY_PASSWORD_ENV}'
     #[clap(long)]
@@ -107,6 +109,9 @@ pub struct BuildCmdArgs {
     #[clap(long)]
     pub target_proxy: Option<ProxyInfo>,
 
+    #[clap(flatten)]
+    pub target_hosts: TargetHostArgs,
+
     /// [OPTION] Target format type. Support 'docker' and 'oci'.
     #[clap(long, short, default_value = "docker")]
     pub format: TargetFormat,
@@ -115,6 +120,10 @@ pub struct BuildCmdArgs {
     #[clap(long, default_value = "600")]
     pub conn_timeout: u64,
 
+    /// [OPTION] Chunk size in MiB of uploading blobs, a failed chunk resumes from the offset received by the registry. 0 uploads a blob in one chunk.
+    #[clap(long, default_value = "64")]
+    pub upload_chunk_size: u64,
+
     /// [OPTION] Compress files using zstd.
     #[clap(long)]
     pub use_zstd: bool,
@@ -122,6 +131,55 @@ pub struct BuildCmdArgs {
     /// [OPTION] Platform.If not specified and there are multiple platforms, the default is 'linux/amd64'.
     #[clap(long)]
     pub platform: Option<Platform>,
+
+    /// [OPTION] Set build-time variables of Dockerfile, can be used multiple times.
+    /// Example:'VERSION=1.0','HTTP_PROXY' (value from environment)
+    #[clap(long = "build-arg")]
+    pub build_args: Vec<BuildArg>,
+
+    /// [OPTION] Name of the Dockerfile stage to output. The last stage is used by default.
+    #[clap(long)]
+    pub target_stage: Option<String>,
+
+    /// [OPTION] Build context directory of Dockerfile. The directory of the Dockerfile is used by default.
+    #[clap(long)]
+    pub context: Option<String>,
+
+    /// [OPTION] Base image of 'cmd:' source. Example:'alpine:3.18','scratch'
+    #[clap(long)]
+    pub from: Option<String>,
+
+    /// [OPTION] Copy files of 'cmd:' source, can be used multiple times. Example:'target/app:/usr/local/bin/'
+    #[clap(long)]
+    pub copy: Vec<CopyArg>,
+
+    /// [OPTION] Environment variables of 'cmd:' source, can be used multiple times. Example:'MODE=prod'
+    #[clap(long)]
+    pub env: Vec<KeyValueArg>,
+
+    /// [OPTION] Labels of 'cmd:' source, can be used multiple times. Example:'version=1.0'
+    #[clap(long)]
+    pub label: Vec<KeyValueArg>,
+
+    /// [OPTION] Cmd of 'cmd:' source, each value is an argument. Example:'--cmd serve --cmd --port=80'
+    #[clap(long, allow_hyphen_values = true)]
+    pub cmd: Vec<String>,
+
+    /// [OPTION] Entrypoint of 'cmd:' source, each value is an argument. Example:'--entrypoint /app'
+    #[clap(long, allow_hyphen_values = true)]
+    pub entrypoint: Vec<String>,
+
+    /// [OPTION] Working directory of 'cmd:' source.
+    #[clap(long)]
+    pub workdir: Option<String>,
+
+    /// [OPTION] User of 'cmd:' source. Example:'nobody','1000:1000'
+    #[clap(long)]
+    pub user: Option<String>,
+
+    /// [OPTION] Exposed ports of 'cmd:' source, can be used multiple times. Example:'80','53/udp'
+    #[clap(long)]
+    pub expose: Vec<String>,
 }
 
 #[derive(clap::Args)]
@@ -134,8 +192,8 @@ pub struct TransformCmdArgs {
     #[clap(long)]
     pub target_allow_insecure: bool,
 
-    /// Source image.
-    /// Example:'my.container.com/source/image:1.0'
+    /// Source image, or a local image with the 'docker-archive:' or 'oci:' prefix.
+    /// Example:'my.container.com/source/image:1.0','docker-archive:/path/image.tar','oci:/path/layout:1.0'
     #[clap(long, short)]
     pub source_image: String,
 
@@ -147,11 +205,11 @@ pub struct TransformCmdArgs {
     #[clap(long)]
     pub source_proxy: Option<ProxyInfo>,
 
-    /// Target type.
-    /// Support 'registry','tar','tgz'
-    /// Example:'registry:my.container.com/target/image:1.1', 'tgz:./image.tgz'
-    #[clap(long, short)]
-    pub target: TargetType,
+    /// Target type, can be used multiple times.
+    /// Support 'registry','docker-archive','oci-archive','oci-dir'
+    /// Example:'registry:my.container.com/target/image:1.1', 'docker-archive:./image.tar:my/image:1.1', 'oci-dir:./layout:1.1'
+    #[clap(long, short, required = true)]
+    pub target: Vec<TargetType>,
 
     /// [OPTION] Auth of push target image. Example:'myname:mypass','myname:${MY_PASSWORD_ENV}'
     #[clap(long)]
@@ -161,6 +219,9 @@ pub struct TransformCmdArgs {
     #[clap(long)]
     pub target_proxy: Option<ProxyInfo>,
 
+    #[clap(flatten)]
+    pub target_hosts: TargetHostArgs,
+
     /// Target format type. Support 'docker' and 'oci'.
     #[clap(long, short)]
     pub format: TargetFormat,
@@ -168,6 +229,10 @@ pub struct TransformCmdArgs {
     /// [OPTION] Connection timeout in seconds.
     #[clap(long, default_value = "600")]
     pub conn_timeout: u64,
+
+    /// [OPTION] Chunk size in MiB of uploading blobs, a failed chunk resumes from the offset received by the registry. 0 uploads a blob in one chunk.
+    #[clap(long, default_value = "64")]
+    pub upload_chunk_size: u64,
 }
 
 impl FromStr for Platform {
@@ -213,7 +278,12 @@ impl FromStr for TargetFormat {
 pub enum SourceType {
     Dockerfile { path: String },
     Registry { image: String },
-    Cmd { tag: String },
+    /// 通过命令行参数构建，不需要Dockerfile
+    Cmd,
+    /// docker save 生成的tar文件
+    DockerArchive { path: String },
+    /// OCI image layout目录
+    OciLayout { path: String, tag: Option<String> },
 }
 
 impl FromStr for SourceType {
@@ -229,9 +299,15 @@ impl FromStr for SourceType {
             "registry" => SourceType::Registry {
                 image: arg[potion + 1..].to_string(),
             },
-            "cmd" => SourceType::Cmd {
-                tag: arg[potion + 1..].to_string(),
+            "docker-archive" => SourceType::DockerArchive {
+                path: arg[potion + 1..].to_string(),
             },
+            "oci" => {
+                let (path, tag) = split_layout_tag(&arg[potion + 1..]);
+                SourceType::OciLayout { path, tag }
+            }
+            "cmd" if arg[potion + 1..].is_empty() => SourceType::Cmd,
+            "cmd" => return Err(anyhow!("'cmd:' source takes no value, use '--from' to set the base image")),
             _ => return Err(anyhow!("unknown source type: {}", source_type)),
         })
     }
@@ -260,13 +336,36 @@ impl FromStr for ProxyInfo {
 #[derive(Clone, Debug)]
 pub enum TargetType {
     Registry(String),
-    Tar(TarArg),
+    /// docker load 可以加载的tar文件
+    DockerArchive(DockerArchiveArg),
+    /// OCI image layout打包成的tar文件
+    OciArchive(OciLayoutArg),
+    /// OCI image layout目录，已经存在时追加镜像
+    OciDir(OciLayoutArg),
 }
 
 #[derive(Clone, Debug)]
-pub struct TarArg {
+pub struct DockerArchiveArg {
     pub path: String,
-    pub usb_gzip: bool,
+    /// 写入 manifest.json 的 RepoTags，例如 'my/app:1.0'
+    pub reference: Option<String>,
+    pub use_gzip: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct OciLayoutArg {
+    pub path: String,
+    pub tag: Option<String>,
+}
+
+impl Display for TargetType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetType::Registry(image) => write!(f, "{}", image),
+            TargetType::DockerArchive(arg) => write!(f, "Path: {}", arg.path),
+            TargetType::OciArchive(arg) | TargetType::OciDir(arg) => write!(f, "Path: {}", arg.path),
+        }
+    }
 }
 
 impl FromStr for TargetType {
@@ -278,19 +377,101 @@ impl FromStr for TargetType {
         let second = arg[potion + 1..].to_string();
         Ok(match target_type {
             "registry" => TargetType::Registry(second),
-            "tar" => TargetType::Tar(TarArg {
-                path: second,
-                usb_gzip: false,
-            }),
-            "tgz" => TargetType::Tar(TarArg {
+            "docker-archive" => {
+                let (path, reference) = match second.split_once(':') {
+                    Some((path, reference)) => (path.to_string(), Some(reference.to_string())),
+                    None => (second, None),
+                };
+                TargetType::DockerArchive(DockerArchiveArg {
+                    path,
+                    reference,
+                    use_gzip: false,
+                })
+            }
+            // 'tar' 和 'tgz' 为之前的写法，和没有设置名称的 'docker-archive' 相同
+            "tar" | "tgz" => TargetType::DockerArchive(DockerArchiveArg {
                 path: second,
-                usb_gzip: true,
+                reference: None,
+                use_gzip: target_type == "tgz",
             }),
+            "oci-archive" => {
+                let (path, tag) = split_layout_tag(&second);
+                TargetType::OciArchive(OciLayoutArg { path, tag })
+            }
+            "oci-dir" => {
+                let (path, tag) = split_layout_tag(&second);
+                TargetType::OciDir(OciLayoutArg { path, tag })
+            }
             _ => return Err(anyhow!("unknown target type: {}", target_type)),
         })
     }
 }
 
+/// 推送到多个目标时，按照仓库host或者镜像仓库路径单独设置的参数，覆盖 '--target-auth' 等默认参数
+#[derive(clap::Args, Clone, Default)]
+pub struct TargetHostArgs {
+    /// [OPTION] Auth of the targets under a registry host or repository path, can be used multiple times, the longest match wins.
+    /// Example:'my.harbor.com=myname:mypass','my.harbor.com/project-a=robot-a:token'
+    #[clap(long)]
+    pub target_host_auth: Vec<HostArg<BaseAuth>>,
+
+    /// [OPTION] Proxy of the targets under a registry host or repository path, can be used multiple times, the longest match wins.
+    /// Example:'ghcr.io=http://127.0.0.1:7890'
+    #[clap(long)]
+    pub target_host_proxy: Vec<HostArg<ProxyInfo>>,
+
+    /// [OPTION] Allow one insecure target registry host, can be used multiple times. Example:'localhost:5000'
+    #[clap(long)]
+    pub target_host_insecure: Vec<String>,
+}
+
+impl TargetHostArgs {
+    pub fn auth(&self, host: &str, repository: &str) -> Option<&BaseAuth> {
+        HostArg::find(&self.target_host_auth, host, repository)
+    }
+
+    pub fn proxy(&self, host: &str, repository: &str) -> Option<&ProxyInfo> {
+        HostArg::find(&self.target_host_proxy, host, repository)
+    }
+
+    pub fn insecure(&self, host: &str) -> bool {
+        self.target_host_insecure.iter().any(|insecure_host| insecure_host == host)
+    }
+}
+
+/// 'host=value' 格式的参数，host后面可以带上仓库路径，只用于这个路径下的镜像，
+/// 例如同一个仓库中的不同项目使用不同的凭证
+#[derive(Clone)]
+pub struct HostArg<T> {
+    pub host: String,
+    pub value: T,
+}
+
+impl<T> HostArg<T> {
+    /// 匹配最长的参数优先，同一个host设置多次时使用最后一个
+    fn find<'a>(args: &'a [HostArg<T>], host: &str, repository: &str) -> Option<&'a T> {
+        let image_path = format!("{}/{}", host, repository);
+        args.iter()
+            .filter(|arg| image_path == arg.host || image_path.starts_with(&format!("{}/", arg.host.trim_end_matches('/'))))
+            .max_by_key(|arg| arg.host.trim_end_matches('/').len())
+            .map(|arg| &arg.value)
+    }
+}
+
+impl<T: FromStr<Err = Error>> FromStr for HostArg<T> {
+    type Err = Error;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg.split_once('=') {
+            Some((host, value)) if !host.is_empty() => Ok(HostArg {
+                host: host.to_string(),
+                value: value.parse()?,
+            }),
+            _ => Err(anyhow!("error value, must be 'HOST=VALUE': {}", arg)),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BaseAuth {
     pub username: String,
@@ -309,6 +490,78 @@ impl FromStr for BaseAuth {
     }
 }
 
+#[derive(Clone)]
+pub struct BuildArg {
+    pub key: String,
+    /// 只传入KEY时从环境变量中读取，环境变量不存在时为None
+    pub value: Option<String>,
+}
+
+impl FromStr for BuildArg {
+    type Err = Error;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (arg, env::var(arg).ok()),
+        };
+        if key.is_empty() {
+            return Err(anyhow!("error build arg: {}", arg));
+        }
+        Ok(BuildArg { key: key.to_string(), value })
+    }
+}
+
+/// 拆分 '/path[:tag]'，':' 之后不包含 '/' 时为tag
+pub fn split_layout_tag(arg: &str) -> (String, Option<String>) {
+    match arg.rsplit_once(':') {
+        Some((path, tag)) if !path.is_empty() && !tag.is_empty() && !tag.contains('/') => (path.to_string(), Some(tag.to_string())),
+        _ => (arg.to_string(), None),
+    }
+}
+
+/// 'cmd:' 来源的 --copy，格式为 'src:dst'
+#[derive(Clone)]
+pub struct CopyArg {
+    pub source: String,
+    pub dest: String,
+}
+
+impl FromStr for CopyArg {
+    type Err = Error;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg.rsplit_once(':') {
+            Some((source, dest)) if !source.is_empty() && !dest.is_empty() => Ok(CopyArg {
+                source: source.to_string(),
+                dest: dest.to_string(),
+            }),
+            _ => Err(anyhow!("error copy, must be 'src:dst': {}", arg)),
+        }
+    }
+}
+
+/// 'KEY=VALUE' 格式的参数
+#[derive(Clone)]
+pub struct KeyValueArg {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for KeyValueArg {
+    type Err = Error;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(KeyValueArg {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(anyhow!("error value, must be 'KEY=VALUE': {}", arg)),
+        }
+    }
+}
+
 fn value_or_env(param: &str) -> Result<String> {
     let value = if param.starts_with("${") && param.ends_with('}') {
         env::var(&param[2..param.len() - 1])?
@@ -323,3 +576,23 @@ fn it_works() -> Result<()> {
     println!("{:?}", value_or_env("${PATH}")?);
     Ok(())
 }
+
+#[test]
+fn multiple_targets_works() -> Result<()> {
+    let args = "ocipack build --source registry:redis:7 --target registry:my.harbor.com/demo/redis:7 \
+        --target registry:my.harbor.com/demo/redis:latest --target docker-archive:redis.tar:redis:7 \
+        --target-auth name:pass --target-host-auth my.harbor.com=harbor:secret --target-host-insecure localhost:5000";
+    let build_args = match CmdArgs::try_parse_from(args.split_whitespace())? {
+        CmdArgs::Build(build_args) => build_args,
+        _ => return Err(anyhow!("not a build command")),
+    };
+    assert_eq!(build_args.target.len(), 3);
+    assert_eq!(build_args.target[2].to_string(), "Path: redis.tar");
+    let hosts = &build_args.target_hosts;
+    assert_eq!(hosts.auth("my.harbor.com", "demo/redis").map(|auth| auth.username.as_str()), Some("harbor"));
+    assert!(hosts.auth("registry-1.docker.io", "library/redis").is_none());
+    assert!(hosts.insecure("localhost:5000"));
+    assert!(!hosts.insecure("my.harbor.com"));
+    assert!("my.harbor.com".parse::<HostArg<BaseAuth>>().is_err());
+    Ok(())
+}
@@ -22,6 +22,7 @@ impl HomeDir {
                     blob_path: blob_cache_dir_path.clone().into_boxed_path(),
                     config_path: blob_cache_dir_path.join("config").into_boxed_path(),
                     layers_path: blob_cache_dir_path.join("layers").into_boxed_path(),
+                    remote_path: blob_cache_dir_path.join("remote").into_boxed_path(),
                     download_dir: cache_dir_path.join("download").into_boxed_path(),
                 },
                 temp_dir: cache_dir_path.join("temp").into_boxed_path(),
@@ -31,6 +32,7 @@ impl HomeDir {
         create_dir_all(&home_dir.cache.blobs.config_path)?;
         create_dir_all(&home_dir.cache.blobs.layers_path)?;
         create_dir_all(&home_dir.cache.blobs.layers_path)?;
+        create_dir_all(&home_dir.cache.blobs.remote_path)?;
         create_dir_all(&home_dir.cache.blobs.download_dir)?;
         Ok(home_dir)
     }
@@ -52,10 +54,13 @@ impl CacheDir {
     }
 }
 
+#[derive(Clone)]
 pub struct BlobsDir {
     pub blob_path: Box<Path>,
     pub config_path: Box<Path>,
     pub layers_path: Box<Path>,
+    /// ADD指令下载的远程文件，以sha256命名
+    pub remote_path: Box<Path>,
     pub download_dir: Box<Path>,
 }
 
@@ -65,6 +70,12 @@ impl BlobsDir {
         file_parent_dir.join(&digest.sha256).into_boxed_path()
     }
 
+    /// 根据sha256查找已经下载过的远程文件
+    pub fn local_remote_file(&self, digest: &RegDigest) -> Option<PathBuf> {
+        let file_path = self.remote_path.join(&digest.sha256);
+        file_path.is_file().then_some(file_path)
+    }
+
     pub fn create_layer_config(
         &self,
         diff_layer_sha256: &str,
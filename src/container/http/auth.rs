@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::option::Option::Some;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -103,6 +104,7 @@ impl AuthenticateAdapter {
             match token_type {
                 TokenType::PushAndPull => url = url + "&scope=repository:" + scope_raw + ":pull,push",
                 TokenType::Pull => url = url + "&scope=repository:" + scope_raw + ":pull",
+                TokenType::Mount(from) => url = url + "&scope=repository:" + scope_raw + ":pull,push&scope=repository:" + &from + ":pull",
             }
         }
         let http_response = do_request_raw::<u8>(client, url.as_str(), Method::GET, basic_auth, &[], None, None)?;
@@ -131,29 +133,26 @@ struct InnerToken {
 pub enum TokenType {
     PushAndPull,
     Pull,
+    /// 跨仓库挂载blob，除了目标仓库的push权限还需要源仓库的pull权限
+    Mount(String),
 }
 
 #[derive(Default)]
 struct TokenCache {
     push_and_pull_map: HashMap<String, InnerToken>,
     pull_map: HashMap<String, InnerToken>,
+    /// key为目标仓库和源仓库
+    mount_map: HashMap<(String, String), InnerToken>,
 }
 
 impl TokenCache {
     pub fn get_token(&mut self, scope: &str, token_type: TokenType) -> Option<String> {
-        match self.get_token_with_type(scope, TokenType::PushAndPull) {
-            None => match token_type {
-                TokenType::PushAndPull => None,
-                TokenType::Pull => self.get_token_with_type(scope, TokenType::Pull),
-            },
-            Some(token) => Some(token),
-        }
-    }
-
-    fn get_token_with_type(&mut self, scope: &str, token_type: TokenType) -> Option<String> {
         match token_type {
-            TokenType::PushAndPull => get_token(scope, &mut self.push_and_pull_map),
-            TokenType::Pull => get_token(scope, &mut self.pull_map),
+            TokenType::PushAndPull => get_token(&scope.to_string(), &mut self.push_and_pull_map),
+            TokenType::Pull => {
+                get_token(&scope.to_string(), &mut self.push_and_pull_map).or_else(|| get_token(&scope.to_string(), &mut self.pull_map))
+            }
+            TokenType::Mount(from) => get_token(&(scope.to_string(), from), &mut self.mount_map),
         }
     }
 
@@ -165,11 +164,12 @@ impl TokenCache {
         match token_type {
             TokenType::PushAndPull => self.push_and_pull_map.insert(scope.to_string(), inner_token),
             TokenType::Pull => self.pull_map.insert(scope.to_string(), inner_token),
+            TokenType::Mount(from) => self.mount_map.insert((scope.to_string(), from), inner_token),
         };
     }
 }
 
-fn get_token(scope: &str, map: &mut HashMap<String, InnerToken>) -> Option<String> {
+fn get_token<K: Eq + Hash>(scope: &K, map: &mut HashMap<K, InnerToken>) -> Option<String> {
     if let Some(inner_token) = map.get(scope) {
         let second_time_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
         if second_time_now < inner_token.expire_second_time {
@@ -180,3 +180,20 @@ fn get_token(scope: &str, map: &mut HashMap<String, InnerToken>) -> Option<Strin
     }
     None
 }
+
+#[test]
+fn mount_token_cache_works() {
+    let mut cache = TokenCache::default();
+    let expire_second_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 60;
+    cache.put_token("team/app", TokenType::PushAndPull, expire_second_time, "push");
+    // 挂载需要源仓库的pull权限，不能使用目标仓库的token
+    assert_eq!(cache.get_token("team/app", TokenType::Mount("base/app".to_string())), None);
+    assert_eq!(cache.get_token("team/app", TokenType::Pull), Some("push".to_string()));
+    cache.put_token("team/app", TokenType::Mount("base/app".to_string()), expire_second_time, "mount");
+    assert_eq!(
+        cache.get_token("team/app", TokenType::Mount("base/app".to_string())),
+        Some("mount".to_string())
+    );
+    assert_eq!(cache.get_token("team/app", TokenType::Mount("other/app".to_string())), None);
+    assert_eq!(cache.get_token("team/app", TokenType::PushAndPull), Some("push".to_string()));
+}
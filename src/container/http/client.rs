@@ -7,12 +7,12 @@ use bytes::Bytes;
 use derive_builder::Builder;
 use reqwest::blocking::{Client, Response};
 use reqwest::redirect::Policy;
-use reqwest::{Method, Proxy, StatusCode};
+use reqwest::{Method, Proxy, StatusCode, Url};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use crate::container::http::auth::{RegTokenHandler, TokenType};
-use crate::container::http::download::RegDownloader;
+use crate::container::http::download::{LayerCache, RegDownloader};
 use crate::container::http::upload::RegUploader;
 use crate::container::http::{do_request_raw, get_header, HttpAuth, RegistryAuth};
 use crate::container::proxy::ProxyInfo;
@@ -32,22 +32,7 @@ impl RegistryHttpClient {
         conn_timeout_second: u64,
         proxy_info: Option<ProxyInfo>,
     ) -> Result<RegistryHttpClient> {
-        let mut builder = reqwest::blocking::ClientBuilder::new();
-        if let Some(info) = proxy_info {
-            let mut proxy_reqwest = Proxy::all(info.addr)?;
-            if let Some(auth) = info.auth {
-                proxy_reqwest = proxy_reqwest.basic_auth(&auth.username, &auth.password)
-            }
-            builder = builder.proxy(proxy_reqwest);
-        }
-        let client = builder
-            .timeout(Duration::from_secs(conn_timeout_second))
-            .gzip(true)
-            .connect_timeout(Duration::from_secs(10))
-            .danger_accept_invalid_certs(true)
-            .deflate(true)
-            .redirect(Policy::default())
-            .build()?;
+        let client = build_http_client(conn_timeout_second, proxy_info, true)?;
         let http_auth_opt = auth.map(|reg_auth| HttpAuth::BasicAuth {
             username: reg_auth.username,
             password: reg_auth.password,
@@ -109,7 +94,14 @@ impl RegistryHttpClient {
         }
     }
 
-    pub fn download(&mut self, path: &str, blob_down_config: BlobConfig, scope: &str, layer_size: Option<u64>) -> Result<RegDownloader> {
+    pub fn download(
+        &mut self,
+        path: &str,
+        blob_down_config: BlobConfig,
+        scope: &str,
+        layer_size: Option<u64>,
+        layer_cache: Option<LayerCache>,
+    ) -> Result<RegDownloader> {
         let url = format!("{}{}", &self.registry_addr, path);
         let token = self.reg_token_handler.token(Some(scope), TokenType::Pull)?;
         let downloader = RegDownloader::new_reg(
@@ -118,11 +110,19 @@ impl RegistryHttpClient {
             self.client.clone(),
             blob_down_config,
             layer_size,
+            layer_cache,
         )?;
         Ok(downloader)
     }
 
-    pub fn upload(&mut self, url: String, blob_config: BlobConfig, scope: &str, file_local_path: &Path) -> Result<RegUploader> {
+    pub fn upload(
+        &mut self,
+        url: String,
+        blob_config: BlobConfig,
+        scope: &str,
+        file_local_path: &Path,
+        chunk_size: u64,
+    ) -> Result<RegUploader> {
         let token = self.reg_token_handler.token(Some(scope), TokenType::PushAndPull)?;
         Ok(RegUploader::new_uploader(
             url,
@@ -130,8 +130,35 @@ impl RegistryHttpClient {
             self.client.clone(),
             blob_config,
             file_local_path.metadata()?.len(),
+            chunk_size,
         ))
     }
+
+    /// 仓库返回的 'Location' 可能是相对地址
+    pub fn resolve_url(&self, location: &str) -> Result<Url> {
+        Ok(Url::parse(&self.registry_addr)?.join(location)?)
+    }
+}
+
+/// 创建一个使用指定代理和超时时间的HTTP Client，'accept_invalid_certs' 为true时不校验证书
+pub fn build_http_client(conn_timeout_second: u64, proxy_info: Option<ProxyInfo>, accept_invalid_certs: bool) -> Result<Client> {
+    let mut builder = reqwest::blocking::ClientBuilder::new();
+    if let Some(info) = proxy_info {
+        let mut proxy_reqwest = Proxy::all(info.addr)?;
+        if let Some(auth) = info.auth {
+            proxy_reqwest = proxy_reqwest.basic_auth(&auth.username, &auth.password)
+        }
+        builder = builder.proxy(proxy_reqwest);
+    }
+    let client = builder
+        .timeout(Duration::from_secs(conn_timeout_second))
+        .gzip(true)
+        .connect_timeout(Duration::from_secs(10))
+        .danger_accept_invalid_certs(accept_invalid_certs)
+        .deflate(true)
+        .redirect(Policy::default())
+        .build()?;
+    Ok(client)
 }
 
 pub struct FullRegistryResponse {
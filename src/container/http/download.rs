@@ -1,22 +1,30 @@
 use std::borrow::BorrowMut;
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
 
 use anyhow::{anyhow, Result};
+use flate2::write::GzDecoder;
+use log::warn;
 use reqwest::blocking::{Client, Response};
-use reqwest::Method;
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
 
-use crate::container::http::{do_request_raw, get_header, HttpAuth};
-use crate::container::BlobConfig;
+use crate::container::home::BlobsDir;
+use crate::container::http::{do_request_raw_read, get_header, HttpAuth};
+use crate::container::{BlobConfig, CompressType};
 use crate::progress::{CoreStatus, ProcessResult, Processor, ProcessorAsync, ProgressStatus};
 
 pub struct RegDownloader {
     finished: bool,
     url: String,
+    layer_size: Option<u64>,
+    layer_cache: Option<LayerCache>,
     auth: Option<HttpAuth>,
     client: Option<Client>,
     temp: RegDownloaderStatus,
@@ -30,6 +38,7 @@ impl RegDownloader {
         client: Client,
         blob_down_config: BlobConfig,
         layer_size: Option<u64>,
+        layer_cache: Option<LayerCache>,
     ) -> Result<RegDownloader> {
         let blob_down_config_arc = Arc::new(blob_down_config);
         let temp = RegDownloaderStatus {
@@ -43,6 +52,8 @@ impl RegDownloader {
         Ok(RegDownloader {
             finished: false,
             url,
+            layer_size,
+            layer_cache,
             auth,
             client: Some(client),
             temp,
@@ -63,6 +74,8 @@ impl RegDownloader {
         Ok(RegDownloader {
             finished: true,
             url: String::default(),
+            layer_size: Some(file_size),
+            layer_cache: None,
             auth: None,
             client: None,
             temp,
@@ -91,17 +104,17 @@ impl Processor<DownloadResult> for RegDownloader {
             url: self.url.clone(),
             auth: self.auth.clone(),
             client: self.client.as_ref().unwrap().clone(),
+            layer_size: self.layer_size,
+            layer_cache: self.layer_cache.clone(),
         };
         let handle = thread::spawn::<_, Result<DownloadResult>>(move || {
             let downloader = reg_http_downloader;
-            let result = downloading(status.clone(), &file_path, downloader);
+            let result = downloading(status.clone(), &file_path, downloader, &blob_config);
             let status_core = &mut status.status_core.lock().unwrap();
             status_core.done = true;
-            if let Err(err) = &result {
-                println!("{}\n{}", err, err.backtrace());
-            }
+            let file_path = result.map_err(|err| anyhow!("{} download failed. {}", blob_config.short_hash, err))?;
             Ok(DownloadResult {
-                file_path: Some(file_path),
+                file_path: Some(file_path.into_boxed_path()),
                 _file_size: status_core.file_size,
                 blob_config: blob_config.clone(),
                 local_existed: false,
@@ -122,8 +135,7 @@ pub struct RegDownloadHandler {
 
 impl ProcessorAsync<DownloadResult> for RegDownloadHandler {
     fn wait_result(self: Box<Self>) -> Result<DownloadResult> {
-        let result = self.join.join();
-        result.unwrap()
+        self.join.join().map_err(|_| anyhow!("join failed."))?
     }
 }
 
@@ -137,36 +149,273 @@ impl ProcessorAsync<DownloadResult> for RegFinishedDownloader {
     }
 }
 
-fn downloading(status: RegDownloaderStatus, file_path: &Path, reg_http_downloader: RegHttpDownloader) -> Result<()> {
+/// 下载中断时保留已经下载的部分，重试和下一次拉取时使用Range继续下载
+const DOWNLOAD_RESUME_TIMES: u32 = 3;
+
+/// 先下载到 '.partial' 文件，sha256和大小都和manifest中的一致后才重命名为最终的文件，返回最终文件的路径
+fn downloading(
+    status: RegDownloaderStatus,
+    file_path: &Path,
+    reg_http_downloader: RegHttpDownloader,
+    blob_config: &BlobConfig,
+) -> Result<PathBuf> {
     //检查本地是否存在已有
     let parent_path = file_path.parent().expect("find file parent dir failed");
     if !parent_path.exists() {
         let _create_result = std::fs::create_dir(parent_path);
     }
+    let partial_path = file_path.with_extension("partial");
+    let mut resume_times = 0;
+    let (sha256, diff_layer_sha) = loop {
+        match download_range(&status, &partial_path, &reg_http_downloader) {
+            Ok(hashes) => break hashes,
+            Err(err) if resume_times < DOWNLOAD_RESUME_TIMES && partial_path.exists() => {
+                resume_times += 1;
+                warn!("Download interrupted, resume from the downloaded part. {}", err);
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    let size = partial_path.metadata()?.len();
+    let expected_digest = &blob_config.reg_digest;
+    if sha256 != expected_digest.sha256 || reg_http_downloader.layer_size.is_some_and(|layer_size| layer_size != size) {
+        fs::remove_file(&partial_path)?;
+        return Err(anyhow!(
+            "downloaded blob does not match, expected digest:{} size:{:?}, actual digest:sha256:{} size:{}",
+            expected_digest.digest,
+            reg_http_downloader.layer_size,
+            sha256,
+            size
+        ));
+    }
+    match (&reg_http_downloader.layer_cache, diff_layer_sha) {
+        (Some(layer_cache), Some(diff_layer_sha)) => {
+            let blobs_dir = &layer_cache.blobs_dir;
+            blobs_dir.move_to_blob(&partial_path, &sha256, &diff_layer_sha)?;
+            let local_layer = blobs_dir.create_layer_config(&diff_layer_sha, &sha256, layer_cache.compress_type)?;
+            Ok(local_layer.layer_file_path)
+        }
+        _ => {
+            fs::rename(&partial_path, file_path)?;
+            Ok(file_path.to_path_buf())
+        }
+    }
+}
+
+/// 从本地已经下载的大小继续下载，仓库或者重定向的地址不支持Range时重新下载完整的文件
+/// 返回整个文件的sha256，下载的是layer时同时返回解压后的sha256
+fn download_range(
+    status: &RegDownloaderStatus,
+    file_path: &Path,
+    reg_http_downloader: &RegHttpDownloader,
+) -> Result<(String, Option<String>)> {
+    let mut offset = file_path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut hasher = BlobHasher::new(reg_http_downloader.layer_cache.as_ref().map(|cache| cache.compress_type))?;
+    if let Some(layer_size) = reg_http_downloader.layer_size {
+        if offset == layer_size {
+            io::copy(&mut File::open(file_path)?, &mut hasher)?;
+            status.status_core.lock().expect("lock failed").curr_size = offset;
+            return hasher.finish();
+        }
+        if offset > layer_size {
+            fs::remove_file(file_path)?;
+            offset = 0;
+        }
+    }
     // 请求HTTP下载
-    let mut http_response = reg_http_downloader.do_request_raw()?;
+    let mut http_response = reg_http_downloader.do_request_raw(offset)?;
+    if offset > 0 && !is_resumed(&http_response, offset) {
+        if http_response.status() != StatusCode::OK {
+            http_response = reg_http_downloader.do_request_raw(0)?;
+        }
+        offset = 0;
+    }
     check(&http_response)?;
-    if let Some(len) = http_response.content_length() {
+    let file = match offset {
+        0 => File::create(file_path)?,
+        _ => {
+            io::copy(&mut File::open(file_path)?, &mut hasher)?;
+            OpenOptions::new().append(true).open(file_path)?
+        }
+    };
+    {
         let mut status_core = status.status_core.lock().expect("lock failed");
-        status_core.borrow_mut().file_size = len;
+        status_core.curr_size = offset;
+        if let Some(len) = http_response.content_length() {
+            status_core.borrow_mut().file_size = offset + len;
+        }
     }
-    let file = File::create(file_path)?;
-    let mut writer = RegDownloaderWriter { status, file };
-    let _copy_size = std::io::copy(&mut http_response, &mut writer)?;
+    let mut writer = RegDownloaderWriter {
+        status: status.clone(),
+        file,
+        hasher,
+    };
+    let _copy_size = io::copy(&mut http_response, &mut writer)?;
     writer.flush()?;
-    Ok(())
+    writer.hasher.finish()
+}
+
+/// 下载的是镜像的layer时，下载完成后直接保存到本地的layer缓存中
+#[derive(Clone)]
+pub struct LayerCache {
+    pub compress_type: CompressType,
+    pub blobs_dir: BlobsDir,
+}
+
+/// 计算下载内容的sha256，是layer时同时解压计算diff_id，只需要读取一次
+struct BlobHasher {
+    sha256: Sha256,
+    diff_id: Option<DiffIdWriter>,
+}
+
+enum DiffIdWriter {
+    Tar(Sha256),
+    Tgz(Box<MultiGzWriter>),
+    Zstd(zstd::stream::write::Decoder<'static, Sha256>),
+}
+
+/// 解压可能有多个member的gzip，和读取时的MultiGzDecoder相同，最后一个member之后的填充数据会被忽略
+struct MultiGzWriter {
+    decoder: Option<GzDecoder<Sha256>>,
+    /// 两个member之间，不在解压时保存hasher
+    hasher: Option<Sha256>,
+    /// 下一个member的头部，用于判断是否为gzip
+    next_header: Vec<u8>,
+    trailing: bool,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl MultiGzWriter {
+    fn new() -> MultiGzWriter {
+        MultiGzWriter {
+            decoder: Some(GzDecoder::new(Sha256::new())),
+            hasher: None,
+            next_header: Vec::with_capacity(GZIP_MAGIC.len()),
+            trailing: false,
+        }
+    }
+
+    fn finish(self) -> io::Result<Sha256> {
+        match (self.decoder, self.hasher) {
+            (Some(decoder), _) => decoder.finish(),
+            (None, Some(hasher)) => Ok(hasher),
+            (None, None) => unreachable!("gzip hasher lost"),
+        }
+    }
+}
+
+impl Write for MultiGzWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut remaining = buf;
+        while !remaining.is_empty() && !self.trailing {
+            match &mut self.decoder {
+                Some(decoder) => match decoder.write(remaining)? {
+                    // 当前member已经结束
+                    0 => self.hasher = Some(self.decoder.take().expect("gzip decoder").finish()?),
+                    size => remaining = &remaining[size..],
+                },
+                None => {
+                    let size = (GZIP_MAGIC.len() - self.next_header.len()).min(remaining.len());
+                    self.next_header.extend_from_slice(&remaining[..size]);
+                    remaining = &remaining[size..];
+                    if self.next_header.len() < GZIP_MAGIC.len() {
+                        continue;
+                    }
+                    if self.next_header == GZIP_MAGIC {
+                        let mut decoder = GzDecoder::new(self.hasher.take().expect("gzip hasher"));
+                        decoder.write_all(&self.next_header)?;
+                        self.decoder = Some(decoder);
+                        self.next_header.clear();
+                    } else {
+                        self.trailing = true;
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl BlobHasher {
+    fn new(compress_type: Option<CompressType>) -> Result<BlobHasher> {
+        let diff_id = match compress_type {
+            None => None,
+            Some(CompressType::Tar) => Some(DiffIdWriter::Tar(Sha256::new())),
+            Some(CompressType::Tgz) => Some(DiffIdWriter::Tgz(Box::new(MultiGzWriter::new()))),
+            Some(CompressType::Zstd) => Some(DiffIdWriter::Zstd(zstd::stream::write::Decoder::new(Sha256::new())?)),
+        };
+        Ok(BlobHasher {
+            sha256: Sha256::new(),
+            diff_id,
+        })
+    }
+
+    fn finish(self) -> Result<(String, Option<String>)> {
+        let diff_id_hasher = match self.diff_id {
+            None => None,
+            Some(DiffIdWriter::Tar(hasher)) => Some(hasher),
+            Some(DiffIdWriter::Tgz(decoder)) => Some(decoder.finish()?),
+            Some(DiffIdWriter::Zstd(mut decoder)) => {
+                decoder.flush()?;
+                Some(decoder.into_inner())
+            }
+        };
+        Ok((
+            hex::encode(self.sha256.finalize()),
+            diff_id_hasher.map(|hasher| hex::encode(hasher.finalize())),
+        ))
+    }
+}
+
+impl Write for BlobHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Digest::update(&mut self.sha256, buf);
+        match &mut self.diff_id {
+            None => {}
+            Some(DiffIdWriter::Tar(hasher)) => Digest::update(hasher, buf),
+            Some(DiffIdWriter::Tgz(decoder)) => decoder.write_all(buf)?,
+            Some(DiffIdWriter::Zstd(decoder)) => decoder.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 返回206并且 'Content-Range' 从请求的位置开始
+fn is_resumed(response: &Response, offset: u64) -> bool {
+    response.status() == StatusCode::PARTIAL_CONTENT
+        && get_header(response.headers(), "Content-Range").and_then(|range| content_range_start(&range)) == Some(offset)
+}
+
+/// 解析 'bytes <start>-<end>/<size>' 中的start
+fn content_range_start(content_range: &str) -> Option<u64> {
+    content_range.strip_prefix("bytes ")?.split_once('-')?.0.trim().parse().ok()
 }
 
 struct RegHttpDownloader {
     url: String,
     auth: Option<HttpAuth>,
     client: Client,
+    layer_size: Option<u64>,
+    layer_cache: Option<LayerCache>,
 }
 
 impl RegHttpDownloader {
-    fn do_request_raw(&self) -> Result<Response> {
+    fn do_request_raw(&self, offset: u64) -> Result<Response> {
         let url = self.url.as_str();
-        do_request_raw::<u8>(&self.client, url, Method::GET, self.auth.as_ref(), &[], None, None)
+        let headers = match offset {
+            0 => Vec::new(),
+            _ => vec![("Range", format!("bytes={}-", offset))],
+        };
+        do_request_raw_read::<io::Empty>(&self.client, url, Method::GET, self.auth.as_ref(), &headers, None, 0)
     }
 }
 
@@ -176,6 +425,9 @@ const OCTET_STREAM_TYPE: [&str; 2] = [
 ];
 
 fn check(response: &Response) -> Result<()> {
+    if !response.status().is_success() {
+        return Err(anyhow!("download request failed, status code:{}", response.status()));
+    }
     let headers = response.headers();
     let content_type = get_header(headers, "content-type").ok_or_else(|| anyhow!("content-type not found"))?;
     if !OCTET_STREAM_TYPE.contains(&content_type.as_str()) {
@@ -184,16 +436,19 @@ fn check(response: &Response) -> Result<()> {
     Ok(())
 }
 
+/// 写入文件的同时计算sha256
 pub struct RegDownloaderWriter {
     status: RegDownloaderStatus,
     file: File,
+    hasher: BlobHasher,
 }
 
 impl Write for RegDownloaderWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let status_core = &mut self.status.status_core.lock().unwrap();
-        status_core.curr_size += buf.len() as u64;
         self.file.write_all(buf)?;
+        self.hasher.write_all(buf)?;
+        // 写入和解压时不持有锁，避免阻塞进度条的刷新
+        self.status.status_core.lock().unwrap().curr_size += buf.len() as u64;
         Ok(buf.len())
     }
     fn flush(&mut self) -> std::io::Result<()> {
@@ -238,3 +493,196 @@ impl ProcessResult for DownloadResult {
         &self.result_str
     }
 }
+
+#[test]
+fn content_range_start_works() {
+    assert_eq!(content_range_start("bytes 1024-2047/2048"), Some(1024));
+    assert_eq!(content_range_start("bytes */2048"), None);
+}
+
+#[test]
+fn multi_member_gzip_works() -> Result<()> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let gzip = |data: &[u8]| -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    };
+    let mut layer = gzip(b"first member ")?;
+    layer.extend(gzip(b"second member")?);
+    let expected = hex::encode(Sha256::digest(b"first member second member"));
+    // 按很小的块写入，member的边界和gzip头部会被拆开
+    for padding in [0, 1, 512] {
+        let mut data = layer.clone();
+        data.resize(layer.len() + padding, 0);
+        for chunk_size in [1, 7, data.len()] {
+            let mut hasher = BlobHasher::new(Some(CompressType::Tgz))?;
+            for chunk in data.chunks(chunk_size) {
+                hasher.write_all(chunk)?;
+            }
+            let (sha256, diff_id) = hasher.finish()?;
+            assert_eq!(sha256, hex::encode(Sha256::digest(&data)));
+            assert_eq!(diff_id.as_deref(), Some(expected.as_str()));
+        }
+    }
+    Ok(())
+}
+
+/// 测试用的镜像仓库，每个连接按顺序返回 responses 中的一个响应，结束后返回每次请求的Range头
+#[cfg(test)]
+fn serve_blob(responses: Vec<Vec<u8>>) -> Result<(String, JoinHandle<Vec<Option<String>>>)> {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/v2/app/blobs/test", listener.local_addr()?);
+    let server = thread::spawn(move || {
+        let mut ranges = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("range:") {
+                    range = Some(value.trim().to_string());
+                }
+                line.clear();
+            }
+            ranges.push(range);
+            // 客户端可能在读完响应之前断开连接
+            let _ = stream.write_all(&response);
+        }
+        ranges
+    });
+    Ok((url, server))
+}
+
+#[cfg(test)]
+fn blob_response(status: &str, headers: &[&str], body: &[u8], content_length: usize) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n",
+        status, content_length
+    );
+    for header in headers {
+        response.push_str(header);
+        response.push_str("\r\n");
+    }
+    response.push_str("Connection: close\r\n\r\n");
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+#[cfg(test)]
+fn test_downloader(url: String, blobs: &BlobsDir, sha256: &str, layer_size: Option<u64>) -> Result<RegDownloader> {
+    use crate::container::RegDigest;
+
+    let digest = RegDigest::new_with_sha256(sha256.to_string());
+    let blob_config = BlobConfig::new(blobs.download_ready(&digest), sha256.to_string(), digest);
+    let layer_cache = LayerCache {
+        compress_type: CompressType::Tgz,
+        blobs_dir: blobs.clone(),
+    };
+    RegDownloader::new_reg(url, None, Client::new(), blob_config, layer_size, Some(layer_cache))
+}
+
+#[cfg(test)]
+fn test_gzip(data: &[u8]) -> Result<Vec<u8>> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[test]
+fn reject_bad_blob_works() -> Result<()> {
+    use crate::container::home::HomeDir;
+    use crate::container::RegDigest;
+    use crate::progress::manager::ProcessorManager;
+
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(temp_dir.path())?;
+    let blobs = &home_dir.cache.blobs;
+    let layer = test_gzip(b"layer content")?;
+    let layer_sha256 = hex::encode(Sha256::digest(&layer));
+    let other = test_gzip(b"other content")?;
+    let other_sha256 = hex::encode(Sha256::digest(&other));
+    // 内容被篡改和大小与manifest不一致的blob都不能进入缓存
+    let (tampered_url, tampered_server) = serve_blob(vec![blob_response("200 OK", &[], &other, other.len())])?;
+    let (short_url, short_server) = serve_blob(vec![blob_response("200 OK", &[], &other, other.len())])?;
+    let processors: Vec<Box<dyn Processor<DownloadResult>>> = vec![
+        Box::new(test_downloader(tampered_url, blobs, &layer_sha256, Some(layer.len() as u64))?),
+        Box::new(test_downloader(short_url, blobs, &other_sha256, Some(other.len() as u64 + 1))?),
+    ];
+    let manager = ProcessorManager::new_processor_manager(processors)?;
+    let err = manager.wait_all_done().err().expect("bad blobs must fail");
+    assert!(err.to_string().starts_with("2 of 2 tasks failed"), "{}", err);
+    tampered_server.join().unwrap();
+    short_server.join().unwrap();
+    for sha256 in [layer_sha256, other_sha256] {
+        assert!(blobs.local_layer(&RegDigest::new_with_sha256(sha256)).is_none());
+    }
+    assert_eq!(fs::read_dir(&blobs.download_dir)?.count(), 0);
+    assert_eq!(fs::read_dir(&blobs.layers_path)?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn resume_download_works() -> Result<()> {
+    use crate::container::home::HomeDir;
+    use crate::container::RegDigest;
+
+    let layer = test_gzip(&(0..2048).map(|index| (index % 251) as u8).collect::<Vec<u8>>())?;
+    let sha256 = hex::encode(Sha256::digest(&layer));
+    let half = layer.len() / 2;
+    let full = blob_response("200 OK", &[], &layer, layer.len());
+    let content_range = format!("Content-Range: bytes {}-{}/{}", half, layer.len() - 1, layer.len());
+    let resumed = blob_response("206 Partial Content", &[&content_range], &layer[half..], layer.len() - half);
+    // (本地已经下载的内容, 仓库的响应, 期望的Range请求头)
+    let cases = [
+        // 206从请求的位置继续，追加到 '.partial' 文件
+        (
+            layer[..half].to_vec(),
+            vec![resumed.clone()],
+            vec![Some(format!("bytes={}-", half))],
+        ),
+        // 下载中断后从已经下载的位置继续
+        (
+            Vec::new(),
+            vec![blob_response("200 OK", &[], &layer[..half], layer.len()), resumed],
+            vec![None, Some(format!("bytes={}-", half))],
+        ),
+        // 不支持Range时返回200，清空文件后重新下载
+        (vec![0; half], vec![full.clone()], vec![Some(format!("bytes={}-", half))]),
+        // 416时不带Range重新请求完整的文件
+        (
+            vec![0; half],
+            vec![blob_response("416 Range Not Satisfiable", &[], &[], 0), full],
+            vec![Some(format!("bytes={}-", half)), None],
+        ),
+    ];
+    for (partial, responses, expected_ranges) in cases {
+        let temp_dir = tempfile::tempdir()?;
+        let home_dir = HomeDir::new_home_dir(temp_dir.path())?;
+        let blobs = &home_dir.cache.blobs;
+        let digest = RegDigest::new_with_sha256(sha256.clone());
+        let partial_path = blobs.download_ready(&digest).with_extension("partial");
+        fs::write(&partial_path, &partial)?;
+        let (url, server) = serve_blob(responses)?;
+        let downloader = test_downloader(url, blobs, &sha256, Some(layer.len() as u64))?;
+        let result = downloader.start().wait_result()?;
+        assert_eq!(server.join().unwrap(), expected_ranges);
+        assert_eq!(fs::read(result.file_path.unwrap())?, layer);
+        assert!(!partial_path.exists());
+        assert!(blobs.local_layer(&digest).is_some());
+        // 重新下载时进度从0开始，不会重复计算已经下载的部分
+        let status = downloader.process_status().status();
+        assert_eq!((status.now_size, status.full_size), (layer.len() as u64, layer.len() as u64));
+    }
+    Ok(())
+}
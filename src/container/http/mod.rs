@@ -12,6 +12,7 @@ use crate::container::RegContentType;
 pub mod auth;
 pub mod client;
 pub mod download;
+pub mod remote;
 pub mod upload;
 
 #[derive(Clone)]
@@ -41,7 +42,8 @@ fn do_request_raw<T: Serialize + ?Sized>(
     content_type: Option<&RegContentType>,
 ) -> Result<Response> {
     let request_body = body.map(|json| RequestBody::Json(json));
-    let request = build_request::<T>(client, url, method, http_auth_opt, accepts, request_body, content_type)?;
+    let headers = content_type.map(|content_type| ("Content-Type", content_type.val().to_string()));
+    let request = build_request::<T>(client, url, method, http_auth_opt, accepts, headers.as_slice(), request_body)?;
     let http_response = client.execute(request)?;
     Ok(http_response)
 }
@@ -51,13 +53,13 @@ fn do_request_raw_read<R: Read + Send + 'static>(
     url: &str,
     method: Method,
     http_auth_opt: Option<&HttpAuth>,
-    accepts: &[RegContentType],
+    headers: &[(&str, String)],
     body: Option<R>,
     size: u64,
 ) -> Result<Response> {
     let request_body = body.map(|read| RequestBody::Read(Body::sized(read, size)));
 
-    let request = build_request::<String>(client, url, method, http_auth_opt, accepts, request_body, None)?;
+    let request = build_request::<String>(client, url, method, http_auth_opt, &[], headers, request_body)?;
     let http_response = client.execute(request)?;
     Ok(http_response)
 }
@@ -68,8 +70,8 @@ fn build_request<T: Serialize + ?Sized>(
     method: Method,
     http_auth_opt: Option<&HttpAuth>,
     accepts: &[RegContentType],
+    headers: &[(&str, String)],
     body: Option<RequestBody<T>>,
-    content_type: Option<&RegContentType>,
 ) -> Result<Request> {
     let url = Url::from_str(url)?;
     let mut builder = client.request(method, url);
@@ -86,8 +88,8 @@ fn build_request<T: Serialize + ?Sized>(
     if !accepts.is_empty() {
         builder = builder.header("Accept", accepts_str.join(","));
     }
-    if let Some(content_type) = content_type {
-        builder = builder.header("Content-Type", content_type.val());
+    for (name, value) in headers {
+        builder = builder.header(*name, value);
     }
     match body {
         None => {}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::info;
+use reqwest::blocking::{Client, Response};
+use reqwest::Method;
+use url::Url;
+
+use crate::container::home::CacheDir;
+use crate::container::http::client::build_http_client;
+use crate::container::http::do_request_raw;
+use crate::container::proxy::ProxyInfo;
+use crate::container::RegDigest;
+use crate::util::random;
+use crate::util::sha::Sha256Writer;
+
+/// 是否为ADD支持的远程地址
+pub fn is_remote_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// 下载ADD指令中的远程文件，使用和Registry相同的代理和超时设置。
+/// 任意地址的文件会写入镜像中，所以和Registry不同，始终校验证书
+pub struct RemoteFileDownloader {
+    client: Client,
+}
+
+impl RemoteFileDownloader {
+    pub fn new(conn_timeout_second: u64, proxy_info: Option<ProxyInfo>) -> Result<RemoteFileDownloader> {
+        Ok(RemoteFileDownloader {
+            client: build_http_client(conn_timeout_second, proxy_info, false)?,
+        })
+    }
+
+    /// 下载远程文件到缓存目录并返回本地路径。
+    /// 指定了checksum时优先使用缓存，下载完成后校验不一致则返回错误
+    pub fn download(&self, url: &str, checksum: Option<&str>, cache: &CacheDir) -> Result<PathBuf> {
+        let expect_digest = checksum.map(parse_checksum).transpose()?;
+        if let Some(digest) = &expect_digest {
+            if let Some(local) = cache.blobs.local_remote_file(digest) {
+                info!("Remote file exists in cache. (url={}, digest={})", url, digest.digest);
+                return Ok(local);
+            }
+        }
+        info!("Downloading remote file... (url={})", url);
+        let mut http_response = do_request_raw::<u8>(&self.client, url, Method::GET, None, &[], None, None)?;
+        let status = http_response.status();
+        if !status.is_success() {
+            return Err(anyhow!("download '{}' failed, status code: {}", url, status));
+        }
+        let temp_file_path = cache.temp_dir.join(random::random_str(10) + ".remote");
+        let digest = match save_response(&mut http_response, &temp_file_path) {
+            Ok(digest) => digest,
+            Err(err) => {
+                let _ = fs::remove_file(&temp_file_path);
+                return Err(anyhow!("download '{}' failed: {}", url, err));
+            }
+        };
+        if let Some(expect_digest) = expect_digest {
+            if expect_digest.sha256 != digest.sha256 {
+                fs::remove_file(&temp_file_path)?;
+                return Err(anyhow!(
+                    "checksum mismatch for '{}', expect '{}' but got '{}'",
+                    url,
+                    expect_digest.digest,
+                    digest.digest
+                ));
+            }
+        }
+        let file_path = cache.blobs.remote_path.join(&digest.sha256);
+        fs::rename(&temp_file_path, &file_path)?;
+        info!("Download remote file complete. (digest={})", digest.digest);
+        Ok(file_path)
+    }
+}
+
+/// 保存响应内容到文件，同时计算sha256
+fn save_response(http_response: &mut Response, file_path: &Path) -> Result<RegDigest> {
+    let mut sha256_writer = Sha256Writer::new(File::create(file_path)?);
+    std::io::copy(http_response, &mut sha256_writer)?;
+    sha256_writer.flush()?;
+    Ok(RegDigest::new_with_sha256(sha256_writer.sha256()?))
+}
+
+/// 远程文件的文件名，取URL路径的最后一段
+pub fn remote_file_name(url: &str) -> Result<String> {
+    let parsed_url = Url::parse(url)?;
+    parsed_url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .ok_or_else(|| anyhow!("can not get file name from url: {}", url))
+}
+
+/// 解析 '--checksum=sha256:...'，目前只支持sha256
+fn parse_checksum(checksum: &str) -> Result<RegDigest> {
+    match checksum.split_once(':') {
+        Some(("sha256", hex)) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(RegDigest::new_with_sha256(hex.to_ascii_lowercase()))
+        }
+        _ => Err(anyhow!("unsupported checksum: {}, only 'sha256:<hex>' is supported", checksum)),
+    }
+}
+
+#[test]
+fn remote_download_works() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    use crate::container::home::HomeDir;
+    use crate::util::sha::bytes_sha256;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let body = "remote file body";
+    let server = std::thread::spawn(move || {
+        // 第一次请求返回不完整的内容，之后只需要响应两次请求，最后一次应该命中缓存
+        for (index, stream) in listener.incoming().take(3).enumerate() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let content_length = if index == 0 { body.len() + 10 } else { body.len() };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                content_length, body
+            )
+            .unwrap();
+        }
+    });
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(temp_dir.path())?;
+    let downloader = RemoteFileDownloader::new(10, None)?;
+    let url = format!("http://{}/files/app.txt", addr);
+    // 下载失败时不保留临时文件
+    assert!(downloader.download(&url, None, &home_dir.cache).is_err());
+    assert_eq!(fs::read_dir(&home_dir.cache.temp_dir)?.count(), 0);
+    let bad_checksum = format!("sha256:{}", bytes_sha256(b"other"));
+    assert!(downloader.download(&url, Some(&bad_checksum), &home_dir.cache).is_err());
+    let checksum = format!("sha256:{}", bytes_sha256(body.as_bytes()));
+    let path = downloader.download(&url, Some(&checksum), &home_dir.cache)?;
+    assert_eq!(fs::read_to_string(&path)?, body);
+    server.join().unwrap();
+    assert_eq!(downloader.download(&url, Some(&checksum), &home_dir.cache)?, path);
+    assert_eq!(remote_file_name(&url)?, "app.txt");
+    Ok(())
+}
@@ -1,5 +1,7 @@
+use std::cmp::min;
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
 use std::ops::DerefMut;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
@@ -7,10 +9,11 @@ use std::thread;
 use std::thread::JoinHandle;
 
 use anyhow::{anyhow, Result};
-use reqwest::blocking::Client;
-use reqwest::Method;
+use log::warn;
+use reqwest::blocking::{Client, Response};
+use reqwest::{Method, Url};
 
-use crate::container::http::{do_request_raw_read, HttpAuth};
+use crate::container::http::{do_request_raw_read, get_header, HttpAuth};
 use crate::container::BlobConfig;
 use crate::progress::{CoreStatus, ProcessResult, Processor, ProcessorAsync, ProgressStatus};
 
@@ -30,10 +33,14 @@ impl ProcessorAsync<UploadResult> for RegFinishedUploader {
     }
 }
 
+/// 上传失败后从仓库记录的位置继续上传的次数
+const UPLOAD_RESUME_TIMES: u32 = 3;
+
 struct RegUploaderCore {
     url: String,
     auth: HttpAuth,
     client: Client,
+    chunk_size: u64,
 }
 
 enum RegUploaderEnum {
@@ -75,7 +82,15 @@ impl RegUploader {
         }
     }
 
-    pub fn new_uploader(url: String, auth: HttpAuth, client: Client, blob_config: BlobConfig, file_size: u64) -> RegUploader {
+    /// 'url' 为POST获取到的上传地址，按照 'chunk_size' 分块PATCH，为0时整个文件作为一块
+    pub fn new_uploader(
+        url: String,
+        auth: HttpAuth,
+        client: Client,
+        blob_config: BlobConfig,
+        file_size: u64,
+        chunk_size: u64,
+    ) -> RegUploader {
         let blob_config_arc = Arc::new(blob_config);
         let temp = RegUploaderStatus {
             status_core: Arc::new(Mutex::new(RegUploaderStatusCore {
@@ -86,7 +101,12 @@ impl RegUploader {
             })),
         };
         RegUploader {
-            reg_uploader_enum: RegUploaderEnum::Run(RegUploaderCore { url, auth, client }),
+            reg_uploader_enum: RegUploaderEnum::Run(RegUploaderCore {
+                url,
+                auth,
+                client,
+                chunk_size,
+            }),
             blob_config: blob_config_arc,
             temp,
         }
@@ -110,6 +130,7 @@ impl Processor<UploadResult> for RegUploader {
                     url: info.url.clone(),
                     auth: info.auth.clone(),
                     client: info.client.clone(),
+                    chunk_size: info.chunk_size,
                 };
                 let file_path_clone = self.blob_config.file_path.to_str().unwrap().to_string();
                 let blob_config_arc = self.blob_config.clone();
@@ -136,37 +157,126 @@ impl Processor<UploadResult> for RegUploader {
     }
 }
 
+/// 按照distribution-spec分块上传：PATCH每一块，失败时GET上传地址获取仓库已经接收的位置后继续，最后PUT带上digest完成上传
 fn uploading(status: RegUploaderStatus, file_path: &str, reg_http_uploader: RegHttpUploader, blob_config: Arc<BlobConfig>) -> Result<()> {
-    //检查本地是否存在已有
     let file_path = Path::new(file_path);
-    let local_file = File::open(file_path)?;
-    let file_size = local_file.metadata()?.len();
-    let reader = RegUploaderReader { status, file: local_file };
-    let mut response = do_request_raw_read::<RegUploaderReader>(
+    let file_size = File::open(file_path)?.metadata()?.len();
+    let chunk_size = match reg_http_uploader.chunk_size {
+        0 => file_size.max(1),
+        chunk_size => chunk_size,
+    };
+    let short_hash = &blob_config.short_hash;
+    let mut session_url = Url::parse(&reg_http_uploader.url)?;
+    let mut offset = 0;
+    let mut resume_times = 0;
+    while offset < file_size {
+        let end = min(offset + chunk_size, file_size);
+        match upload_chunk(&reg_http_uploader, &session_url, &status, file_path, offset, end) {
+            Ok(next_url) => {
+                session_url = next_url;
+                offset = end;
+            }
+            Err(err) if resume_times < UPLOAD_RESUME_TIMES => {
+                resume_times += 1;
+                warn!("{} upload chunk failed, resume from the registry offset. {}", short_hash, err);
+                (session_url, offset) = upload_offset(&reg_http_uploader, &session_url)
+                    .map_err(|resume_err| anyhow!("{} upload can not resume. {}\n{}", short_hash, err, resume_err))?;
+                status.status_core.lock().unwrap().curr_size = offset;
+            }
+            Err(err) => return Err(anyhow!("{} upload request failed. {}", short_hash, err)),
+        }
+    }
+    session_url.query_pairs_mut().append_pair("digest", &blob_config.reg_digest.digest);
+    let response = do_request_raw_read(
         &reg_http_uploader.client,
-        reg_http_uploader.url.as_str(),
+        session_url.as_str(),
         Method::PUT,
         Some(&reg_http_uploader.auth),
         &[],
+        Some(io::empty()),
+        0,
+    )?;
+    check_response(response).map_err(|err| anyhow!("{} upload request failed. {}", short_hash, err))?;
+    Ok(())
+}
+
+/// PATCH文件中 [start, end) 的内容，返回仓库给出的下一次请求的地址
+fn upload_chunk(
+    reg_http_uploader: &RegHttpUploader,
+    session_url: &Url,
+    status: &RegUploaderStatus,
+    file_path: &Path,
+    start: u64,
+    end: u64,
+) -> Result<Url> {
+    let mut file = File::open(file_path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let reader = RegUploaderReader {
+        status: status.clone(),
+        file: file.take(end - start),
+    };
+    let headers = [
+        ("Content-Type", "application/octet-stream".to_string()),
+        ("Content-Range", format!("{}-{}", start, end - 1)),
+    ];
+    let response = do_request_raw_read(
+        &reg_http_uploader.client,
+        session_url.as_str(),
+        Method::PATCH,
+        Some(&reg_http_uploader.auth),
+        &headers,
         Some(reader),
-        file_size,
+        end - start,
     )?;
-    let short_hash = &blob_config.short_hash;
-    if response.status().is_success() {
-        let mut response_string = String::new();
-        let _read_size = response.read_to_string(&mut response_string)?;
+    let location = get_header(response.headers(), "Location");
+    check_response(response)?;
+    next_url(session_url, location)
+}
+
+/// GET上传地址，根据 'Range' 获取仓库已经接收的大小
+fn upload_offset(reg_http_uploader: &RegHttpUploader, session_url: &Url) -> Result<(Url, u64)> {
+    let response = do_request_raw_read::<io::Empty>(
+        &reg_http_uploader.client,
+        session_url.as_str(),
+        Method::GET,
+        Some(&reg_http_uploader.auth),
+        &[],
+        None,
+        0,
+    )?;
+    let location = get_header(response.headers(), "Location");
+    let range = get_header(response.headers(), "Range");
+    check_response(response)?;
+    Ok((next_url(session_url, location)?, range_offset(range.as_deref())))
+}
+
+/// 'Range' 为 '0-<最后一个字节的位置>'，没有接收任何内容时可能没有这个header或者为 '0--1'
+fn range_offset(range: Option<&str>) -> u64 {
+    range.and_then(|range| range.split_once('-')).and_then(|(_, last)| last.parse::<u64>().ok()).map_or(0, |last| last + 1)
+}
+
+/// 'Location' 可能是相对地址，没有返回时继续使用当前地址
+fn next_url(session_url: &Url, location: Option<String>) -> Result<Url> {
+    match location {
+        Some(location) => Ok(session_url.join(&location)?),
+        None => Ok(session_url.clone()),
+    }
+}
+
+fn check_response(mut response: Response) -> Result<()> {
+    let status_code = response.status();
+    let mut response_string = String::new();
+    let _read_size = response.read_to_string(&mut response_string)?;
+    if status_code.is_success() {
         Ok(())
     } else {
-        let _status_code = response.status().as_str();
-        let mut response_string = String::new();
-        let _read_size = response.read_to_string(&mut response_string)?;
-        Err(anyhow!("{} upload request failed. {}", short_hash, response_string))
+        Err(anyhow!("status code:{} {}", status_code, response_string))
     }
 }
 
 pub struct RegUploaderReader {
     status: RegUploaderStatus,
-    file: File,
+    file: io::Take<File>,
 }
 
 impl Read for RegUploaderReader {
@@ -205,6 +315,7 @@ struct RegHttpUploader {
     url: String,
     auth: HttpAuth,
     client: Client,
+    chunk_size: u64,
 }
 
 pub struct UploadResult {
@@ -216,3 +327,132 @@ impl ProcessResult for UploadResult {
         &self.result_str
     }
 }
+
+#[test]
+fn range_offset_works() {
+    assert_eq!(range_offset(Some("0-1023")), 1024);
+    assert_eq!(range_offset(Some("0--1")), 0);
+    assert_eq!(range_offset(None), 0);
+}
+
+/// 测试服务收到的请求：方法、地址、Content-Range和body的大小
+#[cfg(test)]
+type UploadRequest = (String, String, Option<String>, usize);
+
+/// 测试用的上传地址，每个连接按顺序返回 responses 中的一个响应，结束后返回收到的请求
+#[cfg(test)]
+fn serve_upload(responses: Vec<String>) -> Result<(String, JoinHandle<Vec<UploadRequest>>)> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/v2/app/blobs/uploads/0", listener.local_addr()?);
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut parts = request_line.split_whitespace();
+            let (method, target) = (parts.next().unwrap().to_string(), parts.next().unwrap().to_string());
+            let (mut content_range, mut content_length) = (None, 0);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                let (name, value) = line.split_once(':').unwrap();
+                match name.to_ascii_lowercase().as_str() {
+                    "content-range" => content_range = Some(value.trim().to_string()),
+                    "content-length" => content_length = value.trim().parse().unwrap(),
+                    _ => {}
+                }
+                line.clear();
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            requests.push((method, target, content_range, content_length));
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        requests
+    });
+    Ok((url, server))
+}
+
+#[cfg(test)]
+fn upload_response(status: &str, headers: &[&str]) -> String {
+    let headers = headers.iter().map(|header| format!("{}\r\n", header)).collect::<String>();
+    format!("HTTP/1.1 {}\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n", status, headers)
+}
+
+/// 通过 RegUploader 上传 content，返回上传结果和进度中已经上传的大小
+#[cfg(test)]
+fn test_upload(url: String, chunk_size: u64, content: &[u8]) -> Result<(Result<UploadResult>, u64)> {
+    use crate::container::RegDigest;
+    use crate::util::sha::bytes_sha256;
+
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("blob");
+    std::fs::write(&file_path, content)?;
+    let digest = RegDigest::new_with_sha256(bytes_sha256(content));
+    let blob_config = BlobConfig::new(file_path.into_boxed_path(), "blob".to_string(), digest);
+    let auth = HttpAuth::BearerToken {
+        token: "token".to_string(),
+    };
+    let uploader = RegUploader::new_uploader(url, auth, Client::new(), blob_config, content.len() as u64, chunk_size);
+    let result = uploader.start().wait_result();
+    Ok((result, uploader.process_status().status().now_size))
+}
+
+#[test]
+fn chunked_upload_works() -> Result<()> {
+    use crate::util::sha::bytes_sha256;
+
+    let content = b"0123456789";
+    let digest = format!("sha256%3A{}", bytes_sha256(content));
+    let (url, server) = serve_upload(vec![
+        upload_response("202 Accepted", &["Location: /v2/app/blobs/uploads/1", "Range: 0-3"]),
+        // 第二块失败后从仓库已经接收的位置继续，而不是从这一块的开头
+        upload_response("500 Internal Server Error", &[]),
+        upload_response("204 No Content", &["Location: /v2/app/blobs/uploads/2", "Range: 0-5"]),
+        upload_response("202 Accepted", &["Location: /v2/app/blobs/uploads/3?state=abc", "Range: 0-9"]),
+        upload_response("201 Created", &[]),
+    ])?;
+    let (result, uploaded_size) = test_upload(url, 4, content)?;
+    result?;
+    assert_eq!(uploaded_size, 10);
+    let requests = server.join().unwrap();
+    let expected = [
+        ("PATCH", "/v2/app/blobs/uploads/0".to_string(), Some("0-3"), 4),
+        ("PATCH", "/v2/app/blobs/uploads/1".to_string(), Some("4-7"), 4),
+        ("GET", "/v2/app/blobs/uploads/1".to_string(), None, 0),
+        ("PATCH", "/v2/app/blobs/uploads/2".to_string(), Some("6-9"), 4),
+        ("PUT", format!("/v2/app/blobs/uploads/3?state=abc&digest={}", digest), None, 0),
+    ];
+    assert_eq!(requests.len(), expected.len());
+    for ((method, target, content_range, size), (expected_method, expected_target, expected_range, expected_size)) in
+        requests.iter().zip(expected)
+    {
+        assert_eq!(method, expected_method);
+        assert_eq!(target, &expected_target);
+        assert_eq!(content_range.as_deref(), expected_range);
+        assert_eq!(*size, expected_size);
+    }
+    // chunk_size为0时整个文件作为一块
+    let (url, server) = serve_upload(vec![
+        upload_response("202 Accepted", &["Location: /v2/app/blobs/uploads/1"]),
+        upload_response("201 Created", &[]),
+    ])?;
+    let (result, uploaded_size) = test_upload(url, 0, content)?;
+    result?;
+    assert_eq!(uploaded_size, 10);
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].2.as_deref(), Some("0-9"));
+    assert_eq!(requests[1].0, "PUT");
+    // 无法获取仓库的位置时上传失败
+    let (url, server) = serve_upload(vec![
+        upload_response("500 Internal Server Error", &[]),
+        upload_response("404 Not Found", &[]),
+    ])?;
+    assert!(test_upload(url, 4, content)?.0.is_err());
+    server.join().unwrap();
+    Ok(())
+}
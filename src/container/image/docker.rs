@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::container::image::{History, Rootfs};
+use crate::container::image::{HealthConfig, History, Rootfs};
 use crate::container::manifest::{CommonManifestConfig, CommonManifestLayer};
 use crate::container::{ConfigBlob, FindPlatform, Layer, LayerConvert, Platform};
 use serde::Deserialize;
@@ -105,4 +105,12 @@ pub struct Config {
     pub volumes: Option<HashMap<String, Value>>,
     #[serde(rename = "WorkingDir")]
     pub working_dir: Option<String>,
+    #[serde(rename = "Healthcheck")]
+    pub healthcheck: Option<HealthConfig>,
+    #[serde(rename = "StopSignal")]
+    pub stop_signal: Option<String>,
+    #[serde(rename = "Shell")]
+    pub shell: Option<Vec<String>>,
+    #[serde(rename = "OnBuild")]
+    pub on_build: Option<Vec<String>>,
 }
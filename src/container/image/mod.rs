@@ -21,3 +21,19 @@ pub struct Rootfs {
     #[serde(rename = "type")]
     pub type_field: String,
 }
+
+/// 容器的健康检查，时间的单位为纳秒
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// ["NONE"] 表示禁用，["CMD", args...] 或者 ["CMD-SHELL", command]
+    #[serde(rename = "Test")]
+    pub test: Option<Vec<String>>,
+    #[serde(rename = "Interval")]
+    pub interval: Option<u64>,
+    #[serde(rename = "Timeout")]
+    pub timeout: Option<u64>,
+    #[serde(rename = "StartPeriod")]
+    pub start_period: Option<u64>,
+    #[serde(rename = "Retries")]
+    pub retries: Option<u64>,
+}
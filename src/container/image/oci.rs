@@ -2,9 +2,9 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Map, Value};
 
-use crate::container::image::{History, Rootfs};
+use crate::container::image::{HealthConfig, History, Rootfs};
 use crate::container::manifest::{CommonManifestConfig, CommonManifestLayer};
 use crate::container::{ConfigBlob, FindPlatform, Layer, LayerConvert, Platform};
 
@@ -47,6 +47,43 @@ pub struct OciManifestPlatform {
     pub features: Option<Vec<String>>,
 }
 
+/// OCI image layout中 index.json 的 'org.opencontainers.image.ref.name'，即镜像的tag
+pub const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// OCI image layout中的 index.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OciLayoutIndex {
+    pub schema_version: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub manifests: Vec<OciDescriptor>,
+    /// 没有用到的字段，例如 'annotations'、'subject'，追加镜像时原样写回
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OciDescriptor {
+    pub media_type: String,
+    pub size: u64,
+    pub digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<OciManifestPlatform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+    /// 没有用到的字段，例如 'artifactType'、'urls'、'data'
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl OciDescriptor {
+    pub fn ref_name(&self) -> Option<&str> {
+        self.annotations.as_ref()?.get(REF_NAME_ANNOTATION).map(|ref_name| ref_name.as_str())
+    }
+}
+
 impl LayerConvert for OciManifest {
     fn get_layers(&self) -> Vec<Layer> {
         self.layers
@@ -109,5 +146,11 @@ pub struct Config {
     #[serde(rename = "CpuShares")]
     pub cpu_shares: Option<u64>,
     #[serde(rename = "Healthcheck")]
-    pub healthcheck: Option<Value>,
+    pub healthcheck: Option<HealthConfig>,
+    #[serde(rename = "StopSignal")]
+    pub stop_signal: Option<String>,
+    #[serde(rename = "Shell")]
+    pub shell: Option<Vec<String>>,
+    #[serde(rename = "OnBuild")]
+    pub on_build: Option<Vec<String>>,
 }
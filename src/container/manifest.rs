@@ -139,6 +139,20 @@ impl Manifest {
         }
     }
 
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Manifest::OciV1(_) => RegContentType::OCI_MANIFEST.val(),
+            Manifest::DockerV2S2(_) => RegContentType::DOCKER_MANIFEST.val(),
+        }
+    }
+
+    pub fn to_json_string(&self) -> Result<String> {
+        Ok(match self {
+            Manifest::OciV1(oci) => serde_json::to_string(oci)?,
+            Manifest::DockerV2S2(docker) => serde_json::to_string(docker)?,
+        })
+    }
+
     pub fn manifest_type(&self) -> &str {
         match self {
             Manifest::OciV1(_) => "OCI",
@@ -5,22 +5,23 @@ use std::str::FromStr;
 
 use anyhow::{anyhow, Error, Result};
 use colored::Colorize;
-use log::{debug, info};
+use log::{debug, info, warn};
 use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
 use serde_json::{Map, Value};
-use url::Url;
+use url::{Position, Url};
 
 use manifest::Manifest;
 
 use crate::const_data::{DEFAULT_IMAGE_HOST, DOCKER_IO_HOST};
 use crate::container::http::auth::TokenType;
 use crate::container::http::client::{ClientRequest, RawRegistryResponse, RegistryHttpClient, RegistryResponse};
-use crate::container::http::download::RegDownloader;
+use crate::container::http::download::{LayerCache, RegDownloader};
 use crate::container::http::upload::RegUploader;
 use crate::container::http::RegistryAuth;
 use crate::container::image::docker::{DockerConfigBlob, DockerManifest};
 use crate::container::image::oci::{OciConfigBlob, OciManifest};
+use crate::container::image::HealthConfig;
 use crate::container::manifest::{ManifestList, ManifestResponse, ManifestResponseEnum};
 use crate::container::proxy::ProxyInfo;
 use crate::util::sha::bytes_sha256;
@@ -31,6 +32,7 @@ pub mod http;
 pub mod image;
 pub mod manifest;
 pub mod proxy;
+pub mod rootfs;
 
 pub struct Reference<'a> {
     /// Image的名称
@@ -231,7 +233,14 @@ impl MyImageManager {
         Ok((serde_json::from_str::<T>(&str_body)?, str_body))
     }
 
-    pub fn layer_blob_download(&mut self, name: &str, blob_digest: &RegDigest, layer_size: Option<u64>) -> Result<RegDownloader> {
+    /// 下载layer，下载完成时直接保存到本地的layer缓存中
+    pub fn layer_blob_download(
+        &mut self,
+        name: &str,
+        blob_digest: &RegDigest,
+        layer_size: Option<u64>,
+        compress_type: CompressType,
+    ) -> Result<RegDownloader> {
         let url_path = format!("/v2/{}/blobs/{}", name, blob_digest.digest);
         let file_path = GLOBAL_CONFIG.home_dir.cache.blobs.download_ready(blob_digest);
         let file_name = blob_digest.sha256.clone();
@@ -242,12 +251,23 @@ impl MyImageManager {
             let finished = RegDownloader::new_finished(blob_config, layer_len)?;
             return Ok(finished);
         }
-        let downloader = self.reg_client.download(&url_path, blob_config, name, layer_size)?;
+        let layer_cache = LayerCache {
+            compress_type,
+            blobs_dir: GLOBAL_CONFIG.home_dir.cache.blobs.clone(),
+        };
+        let downloader = self.reg_client.download(&url_path, blob_config, name, layer_size, Some(layer_cache))?;
         Ok(downloader)
     }
 
-    /// 上传layer类型的blob文件
-    pub fn layer_blob_upload(&mut self, name: &str, blob_digest: &RegDigest, file_local_path: &str) -> Result<RegUploader> {
+    /// 上传layer类型的blob文件，'mount_from' 为同一个仓库中的源镜像名称，先尝试跨仓库挂载
+    pub fn layer_blob_upload(
+        &mut self,
+        name: &str,
+        blob_digest: &RegDigest,
+        file_local_path: &str,
+        chunk_size: u64,
+        mount_from: Option<&str>,
+    ) -> Result<RegUploader> {
         let file_path = PathBuf::from(file_local_path).into_boxed_path();
         let file_name = file_path.file_name().expect("file name error").to_str().unwrap().to_string();
         let blob_config = BlobConfig::new(file_path.clone(), file_name, blob_digest.clone());
@@ -258,11 +278,27 @@ impl MyImageManager {
                 "blob exists in registry".to_string(),
             ));
         }
-        let mut location_url = self.layer_blob_upload_ready(name)?;
-        location_url.query_pairs_mut().append_pair("digest", &blob_digest.digest);
-        let blob_upload_url = location_url.as_str();
-        debug!("blob_upload_url is {}", blob_upload_url);
-        let reg_uploader = self.reg_client.upload(location_url.to_string(), blob_config, name, &file_path)?;
+        let mut mount_location = None;
+        if let Some(from) = mount_from.filter(|from| *from != name) {
+            match self.layer_blob_mount(name, blob_digest, from) {
+                Ok(None) => {
+                    return Ok(RegUploader::new_finished_uploader(
+                        blob_config,
+                        file_path.metadata()?.len(),
+                        format!("blob mounted from {}", from),
+                    ));
+                }
+                // 仓库不支持挂载或者源仓库中没有这个blob时返回202，直接使用返回的上传地址
+                Ok(Some(location_url)) => mount_location = Some(location_url),
+                Err(err) => warn!("{} mount from {} failed, upload it instead. {}", blob_config.short_hash, from, err),
+            }
+        }
+        let location_url = match mount_location {
+            Some(location_url) => location_url,
+            None => self.layer_blob_upload_ready(name)?,
+        };
+        debug!("blob_upload_url is {}", location_url.as_str());
+        let reg_uploader = self.reg_client.upload(location_url.to_string(), blob_config, name, &file_path, chunk_size)?;
         Ok(reg_uploader)
     }
 
@@ -272,9 +308,23 @@ impl MyImageManager {
         let scope = Some(name);
         let request = ClientRequest::new(&url_path, scope, Method::POST, &[], None, TokenType::PushAndPull);
         let success_resp = self.reg_client.request_full_response::<u8>(request)?;
-        let location = success_resp.location_header().expect("location header not found");
-        let url = Url::parse(location)?;
-        Ok(url)
+        let location = success_resp.location_header().ok_or_else(|| anyhow!("location header not found"))?;
+        self.reg_client.resolve_url(location)
+    }
+
+    /// 从同一个仓库的其他镜像挂载blob，挂载成功时返回None，否则返回上传blob的URL
+    pub fn layer_blob_mount(&mut self, name: &str, blob_digest: &RegDigest, from: &str) -> Result<Option<Url>> {
+        let mut mount_url = self.reg_client.resolve_url(&format!("/v2/{}/blobs/uploads/", name))?;
+        mount_url.query_pairs_mut().append_pair("mount", &blob_digest.digest).append_pair("from", from);
+        let url_path = &mount_url[Position::BeforePath..];
+        let scope = Some(name);
+        let request = ClientRequest::new(url_path, scope, Method::POST, &[], None, TokenType::Mount(from.to_string()));
+        let success_resp = self.reg_client.request_full_response::<u8>(request)?;
+        if *success_resp.status_code() == StatusCode::CREATED {
+            return Ok(None);
+        }
+        let location = success_resp.location_header().ok_or_else(|| anyhow!("location header not found"))?;
+        Ok(Some(self.reg_client.resolve_url(location)?))
     }
 
     pub fn put_manifest(&mut self, refe: &Reference, manifest: Manifest) -> Result<(StatusCode, String)> {
@@ -310,6 +360,10 @@ impl MyImageManager {
     }
 }
 
+fn env_key(env: &str) -> &str {
+    env.split_once('=').map(|(key, _)| key).unwrap_or(env)
+}
+
 fn exited(simple_response: &RawRegistryResponse) -> Result<bool> {
     match simple_response.status_code().as_u16() {
         200..300 => Ok(true),
@@ -364,23 +418,37 @@ impl ConfigBlobEnum {
         };
     }
 
+    /// 同名的变量会覆盖基础镜像中的值
     pub fn add_envs(&mut self, envs: HashMap<String, String>) {
         if envs.is_empty() {
             return;
         }
         let new_envs = envs.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<String>>();
-        match self {
-            ConfigBlobEnum::OciV1(oci) => match &mut oci.config.env {
-                None => oci.config.env = Some(new_envs),
-                Some(source) => source.extend(new_envs),
-            },
-            ConfigBlobEnum::DockerV2S2(docker) => match &mut docker.config.env {
-                None => docker.config.env = Some(new_envs),
-                Some(source) => source.extend(new_envs),
-            },
+        let env = match self {
+            ConfigBlobEnum::OciV1(oci) => &mut oci.config.env,
+            ConfigBlobEnum::DockerV2S2(docker) => &mut docker.config.env,
+        };
+        match env {
+            None => *env = Some(new_envs),
+            Some(source) => {
+                source.retain(|old| !new_envs.iter().any(|new| env_key(old) == env_key(new)));
+                source.extend(new_envs);
+            }
         };
     }
 
+    /// 镜像中的环境变量
+    pub fn envs(&self) -> HashMap<String, String> {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.env.as_deref(),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.env.as_deref(),
+        }
+        .unwrap_or_default()
+        .iter()
+        .map(|env| (env_key(env).to_string(), env.split_once('=').map(|(_, value)| value).unwrap_or_default().to_string()))
+        .collect()
+    }
+
     pub fn overwrite_cmd(&mut self, cmds: Vec<String>) {
         match self {
             ConfigBlobEnum::OciV1(oci) => oci.config.cmd = Some(cmds),
@@ -388,6 +456,20 @@ impl ConfigBlobEnum {
         }
     }
 
+    /// 覆盖ENTRYPOINT，和Docker一样会重置继承自基础镜像的CMD
+    pub fn overwrite_entrypoint(&mut self, entrypoints: Vec<String>) {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => {
+                oci.config.entrypoint = Some(entrypoints);
+                oci.config.cmd = None;
+            }
+            ConfigBlobEnum::DockerV2S2(docker) => {
+                docker.config.entrypoint = Some(entrypoints);
+                docker.config.cmd = None;
+            }
+        }
+    }
+
     pub fn add_ports(&mut self, port_exposes: Vec<String>) {
         if port_exposes.is_empty() {
             return;
@@ -408,6 +490,26 @@ impl ConfigBlobEnum {
         }
     }
 
+    pub fn add_volumes(&mut self, volumes: Vec<String>) {
+        if volumes.is_empty() {
+            return;
+        }
+        let mut map = HashMap::<String, Value>::with_capacity(volumes.len());
+        volumes.into_iter().for_each(|volume| {
+            map.insert(volume, Value::Object(Map::new()));
+        });
+        match self {
+            ConfigBlobEnum::OciV1(oci) => match &mut oci.config.volumes {
+                None => oci.config.volumes = Some(map),
+                Some(source) => source.extend(map),
+            },
+            ConfigBlobEnum::DockerV2S2(docker) => match &mut docker.config.volumes {
+                None => docker.config.volumes = Some(map),
+                Some(source) => source.extend(map),
+            },
+        }
+    }
+
     pub fn overwrite_work_dir(&mut self, work_dir: String) {
         match self {
             ConfigBlobEnum::OciV1(oci) => oci.config.working_dir = Some(work_dir),
@@ -415,6 +517,51 @@ impl ConfigBlobEnum {
         }
     }
 
+    pub fn overwrite_healthcheck(&mut self, healthcheck: HealthConfig) {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.healthcheck = Some(healthcheck),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.healthcheck = Some(healthcheck),
+        }
+    }
+
+    pub fn overwrite_stop_signal(&mut self, stop_signal: String) {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.stop_signal = Some(stop_signal),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.stop_signal = Some(stop_signal),
+        }
+    }
+
+    pub fn overwrite_shell(&mut self, shell: Vec<String>) {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.shell = Some(shell),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.shell = Some(shell),
+        }
+    }
+
+    /// 基础镜像中的ONBUILD触发器
+    pub fn on_build(&self) -> &[String] {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.on_build.as_deref(),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.on_build.as_deref(),
+        }
+        .unwrap_or_default()
+    }
+
+    pub fn overwrite_on_build(&mut self, on_build: Option<Vec<String>>) {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.on_build = on_build,
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.on_build = on_build,
+        }
+    }
+
+    pub fn work_dir(&self) -> Option<&str> {
+        match self {
+            ConfigBlobEnum::OciV1(oci) => oci.config.working_dir.as_deref(),
+            ConfigBlobEnum::DockerV2S2(docker) => docker.config.working_dir.as_deref(),
+        }
+        .filter(|work_dir| !work_dir.is_empty())
+    }
+
     pub fn overwrite_user(&mut self, user: String) {
         match self {
             ConfigBlobEnum::OciV1(oci) => oci.config.user = Some(user),
@@ -470,6 +617,7 @@ impl ConfigBlobEnum {
     }
 }
 
+#[derive(Clone)]
 pub struct ConfigBlobSerialize {
     pub json_str: String,
     pub digest: RegDigest,
@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use tar::{Archive, Entry};
+
+use crate::container::home::LocalLayer;
+use crate::util::compress::uncompress_reader;
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+const MAX_SYMLINK_DEPTH: usize = 16;
+
+/// 从镜像的layer中读取文件内容，layers从底层到顶层排列，上层的文件会覆盖下层
+pub fn read_layer_file(layers: &[LocalLayer], file_path: &str) -> Result<Option<Vec<u8>>> {
+    let mut content = None;
+    walk_layer_files(layers, file_path, |relative_path, entry| {
+        if relative_path.as_os_str().is_empty() && entry.header().entry_type().is_file() {
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf)?;
+            content = Some(buf);
+        }
+        Ok(())
+    })?;
+    Ok(content)
+}
+
+/// 从顶层到底层遍历镜像中 source_path 及其下面的文件，被上层覆盖或者删除(whiteout)的文件会被跳过。
+/// source_path 的父目录可以是软链接。handle 的第一个参数为相对于 source_path 的路径，返回是否找到了文件
+pub fn walk_layer_files<F>(layers: &[LocalLayer], source_path: &str, mut handle: F) -> Result<bool>
+where
+    F: FnMut(&Path, &mut Entry<Box<dyn Read>>) -> Result<()>,
+{
+    let source_path = normalize_path(source_path);
+    if walk_visible_files(layers, &source_path, &mut handle)? {
+        return Ok(true);
+    }
+    // 父目录可能是软链接，例如 usrmerge 镜像中的 /bin -> usr/bin
+    let mut symlinks = HashMap::new();
+    walk_visible_files(
+        layers,
+        Path::new(""),
+        &mut |relative_path: &Path, entry: &mut Entry<Box<dyn Read>>| {
+            if entry.header().entry_type().is_symlink() {
+                if let Some(link_name) = entry.link_name()? {
+                    symlinks.insert(relative_path.to_path_buf(), link_name.into_owned());
+                }
+            }
+            Ok(())
+        },
+    )?;
+    let resolved_path = resolve_parent_links(&symlinks, &source_path);
+    if resolved_path == source_path {
+        return Ok(false);
+    }
+    walk_visible_files(layers, &resolved_path, &mut handle)
+}
+
+/// 遍历所有layer中可见的文件，所有路径都需要记录，上层中 source_path 之外的文件或者whiteout也会覆盖下层的父目录
+fn walk_visible_files<F>(layers: &[LocalLayer], source_path: &Path, handle: &mut F) -> Result<bool>
+where
+    F: FnMut(&Path, &mut Entry<Box<dyn Read>>) -> Result<()>,
+{
+    let mut found = false;
+    let mut seen_paths = HashSet::<PathBuf>::new();
+    let mut non_dir_paths = HashSet::<PathBuf>::new();
+    let mut deleted_paths = Vec::<PathBuf>::new();
+    let mut opaque_dirs = Vec::<PathBuf>::new();
+    for layer in layers.iter().rev() {
+        // whiteout只对下层生效
+        let mut layer_deleted_paths = Vec::new();
+        let mut layer_opaque_dirs = Vec::new();
+        let reader = uncompress_reader(layer.compress_type, File::open(&layer.layer_file_path)?)?;
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_pax_global_extensions() || entry_type.is_pax_local_extensions() {
+                continue;
+            }
+            let entry_path = normalize_path(&entry.path()?.to_string_lossy());
+            let hidden = seen_paths.contains(&entry_path)
+                || deleted_paths.iter().any(|deleted| entry_path.starts_with(deleted))
+                || opaque_dirs.iter().any(|dir| entry_path != *dir && entry_path.starts_with(dir))
+                || entry_path.ancestors().skip(1).any(|ancestor| non_dir_paths.contains(ancestor));
+            if hidden {
+                continue;
+            }
+            let file_name = entry_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            let parent = entry_path.parent().map(Path::to_path_buf).unwrap_or_default();
+            if file_name == OPAQUE_WHITEOUT {
+                layer_opaque_dirs.push(parent);
+                continue;
+            }
+            if let Some(deleted_name) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+                layer_deleted_paths.push(parent.join(deleted_name));
+                continue;
+            }
+            if !entry_type.is_dir() {
+                non_dir_paths.insert(entry_path.clone());
+            }
+            seen_paths.insert(entry_path.clone());
+            if let Ok(relative_path) = entry_path.strip_prefix(source_path) {
+                found = true;
+                handle(relative_path, &mut entry)?;
+            }
+        }
+        deleted_paths.extend(layer_deleted_paths);
+        opaque_dirs.extend(layer_opaque_dirs);
+    }
+    Ok(found)
+}
+
+/// 解析路径中是软链接的父目录，最后一级不解析
+fn resolve_parent_links(symlinks: &HashMap<PathBuf, PathBuf>, path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    'resolve: for _ in 0..MAX_SYMLINK_DEPTH {
+        let components = path.components().collect::<Vec<Component>>();
+        let mut prefix = PathBuf::new();
+        for (index, component) in components.iter().enumerate().take(components.len().saturating_sub(1)) {
+            prefix.push(component);
+            if let Some(target) = symlinks.get(&prefix) {
+                let mut resolved = resolve_link(&prefix, target);
+                resolved.extend(&components[index + 1..]);
+                path = resolved;
+                continue 'resolve;
+            }
+        }
+        break;
+    }
+    path
+}
+
+/// 镜像中的目录和软链接，用于判断COPY的目标路径是否为已经存在的目录，
+/// 同时保存passwd和group文件的内容，用于解析 --chown 中的名称。
+/// 第一次查询时才读取layers，整个构建只读取一次
+pub struct LayerDirs<'a> {
+    layers: &'a [LocalLayer],
+    loaded: bool,
+    dirs: HashSet<PathBuf>,
+    symlinks: HashMap<PathBuf, PathBuf>,
+    /// 文件名为passwd或group的文件，/etc 可能是软链接，所以不只保存 /etc 中的文件
+    id_files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl<'a> LayerDirs<'a> {
+    pub fn new(layers: &'a [LocalLayer]) -> LayerDirs<'a> {
+        LayerDirs {
+            layers,
+            loaded: false,
+            dirs: HashSet::new(),
+            symlinks: HashMap::new(),
+            id_files: HashMap::new(),
+        }
+    }
+
+    /// 添加新创建的目录以及它的所有父目录
+    pub fn add_dir(&mut self, path: &Path) {
+        let path = normalize_path(&path.to_string_lossy());
+        for ancestor in path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+            self.dirs.insert(ancestor.to_path_buf());
+        }
+    }
+
+    /// 判断路径是否为目录，会跟随软链接
+    pub fn is_dir(&mut self, path: &Path) -> Result<bool> {
+        if !self.loaded {
+            self.load()?;
+        }
+        let mut path = normalize_path(&path.to_string_lossy());
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            if path.as_os_str().is_empty() || self.dirs.contains(&path) {
+                return Ok(true);
+            }
+            match self.symlinks.get(&path) {
+                Some(target) => path = resolve_link(&path, target),
+                None => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+
+    /// 解析 --chown 的 'user:group' 参数，名称从镜像的 /etc/passwd 和 /etc/group 中查找。
+    /// 没有指定group时，gid和uid相同
+    pub fn resolve_owner(&mut self, chown: &str) -> Result<(u64, u64)> {
+        let (user, group) = match chown.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (chown, None),
+        };
+        let uid = match user.parse::<u64>() {
+            Ok(uid) => uid,
+            Err(_) => self.lookup_id("etc/passwd", user)?,
+        };
+        let gid = match group {
+            None => uid,
+            Some(group) => match group.parse::<u64>() {
+                Ok(gid) => gid,
+                Err(_) => self.lookup_id("etc/group", group)?,
+            },
+        };
+        Ok((uid, gid))
+    }
+
+    /// 在passwd或group格式的文件中按名称查找id，两种格式的第三列都是id
+    fn lookup_id(&mut self, file_path: &str, name: &str) -> Result<u64> {
+        let content = self.id_file(file_path)?.ok_or_else(|| anyhow!("can not found /{} in base image", file_path))?;
+        String::from_utf8_lossy(content)
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<&str>>())
+            .find(|fields| fields.len() > 2 && fields[0] == name)
+            .map(|fields| fields[2].parse::<u64>())
+            .transpose()?
+            .ok_or_else(|| anyhow!("can not found '{}' in /{} of base image", name, file_path))
+    }
+
+    /// 跟随父目录和文件本身的软链接查找passwd或group文件
+    fn id_file(&mut self, file_path: &str) -> Result<Option<&Vec<u8>>> {
+        if !self.loaded {
+            self.load()?;
+        }
+        let mut path = resolve_parent_links(&self.symlinks, Path::new(file_path));
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            match self.symlinks.get(&path) {
+                Some(target) => path = resolve_parent_links(&self.symlinks, &resolve_link(&path, target)),
+                None => break,
+            }
+        }
+        // 软链接指向的文件名不是passwd或group时才需要再读取一次
+        if !self.id_files.contains_key(&path) {
+            if let Some(content) = read_layer_file(self.layers, &path.to_string_lossy())? {
+                self.id_files.insert(path.clone(), content);
+            }
+        }
+        Ok(self.id_files.get(&path))
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let mut dirs = Vec::new();
+        let mut symlinks = Vec::new();
+        let mut id_files = HashMap::new();
+        walk_layer_files(self.layers, "/", |relative_path, entry| {
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                dirs.push(relative_path.to_path_buf());
+            } else if entry_type.is_file() && relative_path.file_name().is_some_and(|name| name == "passwd" || name == "group") {
+                let mut content = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut content)?;
+                id_files.insert(relative_path.to_path_buf(), content);
+            } else if entry_type.is_symlink() {
+                if let Some(link_name) = entry.link_name()? {
+                    symlinks.push((relative_path.to_path_buf(), link_name.into_owned()));
+                }
+            }
+            // 没有目录entry的父目录也是目录
+            if let Some(parent) = relative_path.parent() {
+                dirs.push(parent.to_path_buf());
+            }
+            Ok(())
+        })?;
+        for dir in dirs {
+            self.add_dir(&dir);
+        }
+        self.symlinks.extend(symlinks);
+        self.id_files = id_files;
+        self.loaded = true;
+        Ok(())
+    }
+}
+
+/// 获取软链接指向的路径，相对路径基于软链接所在的目录
+fn resolve_link(link_path: &Path, target: &Path) -> PathBuf {
+    let mut resolved = if target.is_absolute() {
+        PathBuf::new()
+    } else {
+        link_path.parent().map(Path::to_path_buf).unwrap_or_default()
+    };
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            _ => {}
+        }
+    }
+    resolved
+}
+
+/// 去掉路径中的根目录和 '.'
+fn normalize_path(path: &str) -> PathBuf {
+    PathBuf::from(path).components().filter(|component| matches!(component, Component::Normal(_))).collect()
+}
+
+#[cfg(test)]
+fn test_layer(dir: &Path, entries: &[(&str, &str)]) -> Result<LocalLayer> {
+    test_layer_with_links(dir, entries, &[])
+}
+
+#[cfg(test)]
+fn test_layer_with_links(dir: &Path, entries: &[(&str, &str)], links: &[(&str, &str)]) -> Result<LocalLayer> {
+    use crate::container::CompressType;
+    let layer_path = dir.join(crate::util::random::random_str(10) + ".tar");
+    let mut builder = tar::Builder::new(File::create(&layer_path)?);
+    for (path, content) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, content.as_bytes())?;
+    }
+    for (path, target) in links {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, path, target)?;
+    }
+    builder.finish()?;
+    let layer = LocalLayer::new("layer".to_string(), "layer".to_string(), CompressType::Tar, dir);
+    Ok(LocalLayer {
+        layer_file_path: layer_path,
+        ..layer
+    })
+}
+
+#[test]
+fn resolve_owner_works() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let layers = [test_layer(
+        temp_dir.path(),
+        &[
+            ("etc/passwd", "root:x:0:0::/root:/bin/sh\napp:x:1001:1002::/app:/bin/sh\n"),
+            ("./etc/group", "app:x:1003:\n"),
+        ],
+    )?];
+    let mut layer_dirs = LayerDirs::new(&layers);
+    assert_eq!(layer_dirs.resolve_owner("app:app")?, (1001, 1003));
+    // passwd和group只在第一次使用时读取
+    std::fs::remove_file(&layers[0].layer_file_path)?;
+    assert_eq!(layer_dirs.resolve_owner("app")?, (1001, 1001));
+    assert!(layer_dirs.resolve_owner("10:root").is_err());
+    assert_eq!(LayerDirs::new(&[]).resolve_owner("10:20")?, (10, 20));
+    // /etc 或者passwd本身是软链接
+    let layers = [test_layer_with_links(
+        temp_dir.path(),
+        &[
+            ("usr/share/base/passwd", "app:x:7:7::/:/bin/sh\n"),
+            ("usr/share/base/group.real", "app:x:8:\n"),
+        ],
+        &[("etc", "usr/share/base"), ("usr/share/base/group", "group.real")],
+    )?];
+    assert_eq!(LayerDirs::new(&layers).resolve_owner("app:app")?, (7, 8));
+    Ok(())
+}
+
+#[test]
+fn walk_layer_files_works() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let layers = [
+        test_layer(
+            temp_dir.path(),
+            &[("app/a", "old"), ("app/b", "b"), ("app/c/d", "d"), ("app/e/f", "f"), ("other", "x")],
+        )?,
+        test_layer(
+            temp_dir.path(),
+            &[
+                ("app/a", "new"),
+                ("app/.wh.b", ""),
+                ("app/c/.wh..wh..opq", ""),
+                ("app/c/g", "g"),
+                ("app/e", "file"),
+            ],
+        )?,
+    ];
+    let mut files = Vec::new();
+    let found = walk_layer_files(&layers, "/app", |relative_path, entry| {
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        files.push(format!("{}={}", relative_path.to_string_lossy(), content));
+        Ok(())
+    })?;
+    assert!(found);
+    files.sort();
+    assert_eq!(files, vec!["a=new", "c/g=g", "e=file"]);
+    assert!(!walk_layer_files(&layers, "/app/b", |_, _| Ok(()))?);
+    Ok(())
+}
+
+#[test]
+fn layer_dirs_works() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let layers = [test_layer(temp_dir.path(), &[("usr/bin/env", "x"), ("etc/app.conf", "x")])?];
+    let mut layer_dirs = LayerDirs::new(&layers);
+    assert!(layer_dirs.is_dir(Path::new("/usr/bin"))?);
+    assert!(!layer_dirs.is_dir(Path::new("/etc/app.conf"))?);
+    assert!(!layer_dirs.is_dir(Path::new("/opt/app"))?);
+    layer_dirs.add_dir(Path::new("opt/app"));
+    assert!(layer_dirs.is_dir(Path::new("/opt"))?);
+    layer_dirs.symlinks.insert(PathBuf::from("bin"), PathBuf::from("usr/bin"));
+    assert!(layer_dirs.is_dir(Path::new("/bin"))?);
+    Ok(())
+}
+
+#[test]
+fn walk_replaced_parent_works() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let layers = [
+        test_layer(temp_dir.path(), &[("app/conf/a", "a"), ("app/data/b", "b"), ("app/keep/c", "c")])?,
+        test_layer_with_links(
+            temp_dir.path(),
+            &[("app/conf", "file"), ("app/.wh.data", "")],
+            &[("app/keep", "/other")],
+        )?,
+    ];
+    // 上层把父目录替换为文件、软链接或者删除后，下层目录中的文件不可见
+    assert!(!walk_layer_files(&layers, "/app/conf/a", |_, _| Ok(()))?);
+    assert!(!walk_layer_files(&layers, "/app/data/b", |_, _| Ok(()))?);
+    assert!(!walk_layer_files(&layers, "/app/keep/c", |_, _| Ok(()))?);
+    Ok(())
+}
+
+#[test]
+fn walk_symlink_parent_works() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let layers = [
+        test_layer_with_links(
+            temp_dir.path(),
+            &[("usr/bin/sh", "shell"), ("usr/lib/app/x.so", "x")],
+            &[("bin", "usr/bin"), ("lib", "/usr/lib")],
+        )?,
+        test_layer_with_links(temp_dir.path(), &[], &[("opt/app", "../lib/app")])?,
+    ];
+    let read = |source_path: &str| -> Result<Option<Vec<String>>> {
+        let mut files = Vec::new();
+        let found = walk_layer_files(&layers, source_path, |relative_path, entry| {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            files.push(format!("{}={}", relative_path.to_string_lossy(), content));
+            Ok(())
+        })?;
+        Ok(found.then_some(files))
+    };
+    // usrmerge 镜像中 /bin 是指向 usr/bin 的软链接
+    assert_eq!(read("/bin/sh")?, Some(vec!["=shell".to_string()]));
+    assert_eq!(read("/opt/app/x.so")?, Some(vec!["=x".to_string()]));
+    assert_eq!(read("/bin/bash")?, None);
+    Ok(())
+}
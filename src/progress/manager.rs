@@ -1,7 +1,7 @@
 use std::thread::sleep;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::bar::{Bar, MultiBar};
 use crate::progress::{ProcessResult, Processor, ProcessorAsync, ProgressStatus};
@@ -37,18 +37,27 @@ impl<R: ProcessResult> ProcessorManager<R> {
 
     pub fn wait_all_done(mut self) -> Result<Vec<R>> {
         println!();
+        let total = self.statuses.len();
         let mut statuses = self.statuses;
         let mut result_infos = Vec::<R>::new();
+        // 等待所有任务结束后再一起返回失败的任务
+        let mut errors = Vec::<anyhow::Error>::new();
         while !statuses.is_empty() {
             let mut new_status: Vec<(Box<dyn ProcessorAsync<R>>, Box<dyn ProgressStatus>, Bar)> = Vec::new();
             for (processor, progress_status, mut bar) in statuses {
                 let status = &progress_status.status();
                 bar.set_size(status.now_size, status.full_size);
                 if status.is_done {
-                    let process_result = processor.wait_result()?;
-                    let finished_info = process_result.finished_info();
-                    bar.finish(true, finished_info);
-                    result_infos.push(process_result);
+                    match processor.wait_result() {
+                        Ok(process_result) => {
+                            bar.finish(true, process_result.finished_info());
+                            result_infos.push(process_result);
+                        }
+                        Err(err) => {
+                            bar.finish(false, "failed");
+                            errors.push(err);
+                        }
+                    }
                 } else {
                     new_status.push((processor, progress_status, bar))
                 }
@@ -61,6 +70,10 @@ impl<R: ProcessResult> ProcessorManager<R> {
             sleep(Duration::from_secs(1));
         }
         println!();
+        if !errors.is_empty() {
+            let messages = errors.iter().map(|err| err.to_string()).collect::<Vec<_>>();
+            return Err(anyhow!("{} of {} tasks failed:\n{}", errors.len(), total, messages.join("\n")));
+        }
         Ok(result_infos)
     }
 }
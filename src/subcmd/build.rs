@@ -1,22 +1,32 @@
-use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{DirEntry, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use colored::Colorize;
-use log::info;
-use tar::Builder;
+use log::{info, warn};
+use tar::{Archive, Builder, EntryType, Header};
 
+use crate::adapter::cmd::CmdAdapter;
 use crate::adapter::docker::DockerfileAdapter;
-use crate::adapter::registry::RegistryTargetAdapter;
-use crate::adapter::tar::TarTargetAdapter;
-use crate::adapter::{BuildInfo, CopyFile, SourceInfo};
-use crate::config::cmd::{BuildCmdArgs, SourceType, TargetFormat, TargetType};
+use crate::adapter::oci_dir::OciDirTargetAdapter;
+use crate::adapter::registry::{parse_target_image, registry_host, RegistryConnection, RegistryTargetAdapter};
+use crate::adapter::tar::{DockerArchiveTargetAdapter, OciArchiveTargetAdapter};
+use crate::adapter::{BuildInfo, CopyFile, CopyFrom, ImageInfo, LocalSource, SourceInfo, StageInfo};
+use crate::config::cmd::{BaseAuth, BuildCmdArgs, SourceType, TargetFormat, TargetHostArgs, TargetType};
 use crate::config::RegAuthType;
 use crate::container::home::{LocalLayer, TempLayerInfo};
+use crate::container::http::remote::{is_remote_url, remote_file_name, RemoteFileDownloader};
 use crate::container::manifest::Manifest;
 use crate::container::proxy::ProxyInfo;
-use crate::container::{CompressType, ConfigBlobEnum, ConfigBlobSerialize};
-use crate::subcmd::pull::pull;
+use crate::container::rootfs::{walk_layer_files, LayerDirs};
+use crate::container::{CompressType, ConfigBlobEnum, ConfigBlobSerialize, RegDigest};
+use crate::subcmd::pull::pull_source;
+use crate::util::dockerignore::DockerIgnore;
+use crate::util::file::container_abs_path;
+use crate::util::glob::{has_magic, match_segment};
 use crate::util::sha::{Sha256Reader, Sha256Writer};
 use crate::util::{compress, random};
 use crate::{HomeDir, GLOBAL_CONFIG};
@@ -51,10 +61,7 @@ Build job successful!
 Target image:
 {}
 "#,
-            match &build_args.target {
-                TargetType::Registry(r) => (*r).clone(),
-                TargetType::Tar(tar_arg) => format!("Path: {}", tar_arg.path),
-            }
+            build_args.target.iter().map(|target| target.to_string()).collect::<Vec<_>>().join("\n")
         )
         .green()
     );
@@ -76,32 +83,82 @@ Build job failed!
 }
 
 fn build_source_info(build_args: &BuildCmdArgs) -> Result<(SourceInfo, BuildInfo, RegAuthType)> {
+    if !matches!(build_args.source, SourceType::Cmd) {
+        let options = cmd_only_options(build_args);
+        if !options.is_empty() {
+            return Err(anyhow!("{} can only be used with the 'cmd:' source", options.join(", ")));
+        }
+    }
+    let mut local = None;
     let (mut image_info, build_info) = match &build_args.source {
-        SourceType::Dockerfile { path } => DockerfileAdapter::parse(path)?,
-        SourceType::Cmd { tag: _ } => {
-            todo!()
+        SourceType::Dockerfile { path } => {
+            let dockerfile_args = build_args
+                .build_args
+                .iter()
+                .filter_map(|arg| arg.value.clone().map(|value| (arg.key.clone(), value)))
+                .collect::<HashMap<String, String>>();
+            DockerfileAdapter::parse(path, &dockerfile_args, build_args.target_stage.as_deref())?
+        }
+        SourceType::Cmd => CmdAdapter::parse(build_args)?,
+        SourceType::DockerArchive { path } => {
+            let local_source = LocalSource::DockerArchive(PathBuf::from(path));
+            let image_info = local_source.image_info();
+            local = Some(local_source);
+            (image_info, BuildInfo::default())
+        }
+        SourceType::OciLayout { path, tag } => {
+            let local_source = LocalSource::OciLayout {
+                path: PathBuf::from(path),
+                tag: tag.clone(),
+            };
+            let image_info = local_source.image_info();
+            local = Some(local_source);
+            (image_info, BuildInfo::default())
         }
         SourceType::Registry { image } => {
             let fake_dockerfile_body = format!("FROM {}", image);
             DockerfileAdapter::parse_from_str(&fake_dockerfile_body)?
         }
     };
-    // add library
-    let image_name = &image_info.image_name;
-    if !image_name.contains('/') {
-        image_info.image_name = format!("library/{}", image_name)
-    }
+    add_library_namespace(&mut image_info);
     let source_reg_auth = RegAuthType::build_auth(image_info.image_host.clone(), build_args.source_auth.as_ref());
     Ok((
         SourceInfo {
             image_info,
             platform: build_args.platform.clone(),
+            local,
         },
         build_info,
         source_reg_auth,
     ))
 }
 
+/// 只用于 'cmd:' 来源的参数中设置了的参数，其他来源会忽略这些参数
+fn cmd_only_options(build_args: &BuildCmdArgs) -> Vec<&'static str> {
+    [
+        ("'--from'", build_args.from.is_some()),
+        ("'--copy'", !build_args.copy.is_empty()),
+        ("'--env'", !build_args.env.is_empty()),
+        ("'--label'", !build_args.label.is_empty()),
+        ("'--cmd'", !build_args.cmd.is_empty()),
+        ("'--entrypoint'", !build_args.entrypoint.is_empty()),
+        ("'--workdir'", build_args.workdir.is_some()),
+        ("'--user'", build_args.user.is_some()),
+        ("'--expose'", !build_args.expose.is_empty()),
+    ]
+    .into_iter()
+    .filter_map(|(option, is_set)| is_set.then_some(option))
+    .collect()
+}
+
+/// Docker Hub的官方镜像需要加上 'library/'
+fn add_library_namespace(image_info: &mut ImageInfo) {
+    let image_name = &image_info.image_name;
+    if !image_name.contains('/') {
+        image_info.image_name = format!("library/{}", image_name)
+    }
+}
+
 fn handle(
     source_info: SourceInfo,
     build_info: BuildInfo,
@@ -111,93 +168,425 @@ fn handle(
     use_zstd: bool,
 ) -> Result<()> {
     let home_dir = GLOBAL_CONFIG.home_dir.clone();
-    let pull_result = pull(
+    let remote_downloader = RemoteFileDownloader::new(build_cmds.conn_timeout, proxy_info.clone())?;
+    let pull_result = pull_source(
         &source_info,
         source_auth,
         !build_cmds.allow_insecure,
         build_cmds.conn_timeout,
         proxy_info,
     )?;
+    let build_info = DockerfileAdapter::apply_on_build(build_info, pull_result.config_blob.on_build())?
+        .expand_base_envs(&pull_result.config_blob.envs())?;
     let compress_type = if use_zstd { CompressType::Zstd } else { CompressType::Tgz };
-    let temp_layer = build_top_tar(&build_info.copy_files, &home_dir)?
-        .map(|tar_path| compress_layer_file(&tar_path, &home_dir, compress_type))
-        .transpose()?;
-    let temp_local_layer = if let Some(temp_layer) = &temp_layer {
-        home_dir.cache.blobs.move_to_blob(
-            &temp_layer.compress_layer_path,
-            &temp_layer.compressed_tar_sha256,
-            &temp_layer.tar_sha256,
-        )?;
-        let temp_local_layer =
-            home_dir.cache.blobs.create_layer_config(&temp_layer.tar_sha256, &temp_layer.compressed_tar_sha256, compress_type)?;
-        Some(temp_local_layer)
-    } else {
-        None
+    let context_dir = build_context_dir(build_cmds)?;
+    let build_context = BuildContext {
+        build_cmds,
+        source_info: &source_info,
+        home_dir: &home_dir,
+        remote_downloader: &remote_downloader,
+        compress_type,
+        docker_ignore: DockerIgnore::load(&context_dir)?,
+        context_dir,
     };
-    let target_config_blob = build_target_config_blob(build_info, &pull_result.config_blob, temp_layer.as_ref(), &build_cmds.format);
+    let base_layers = local_layers(&pull_result.manifest, &home_dir)?;
+    let mut copy_sources = HashMap::<CopyFrom, Vec<LocalLayer>>::new();
+    prepare_copy_sources(&build_info.copy_files, &build_info.stages, &build_context, &mut copy_sources)?;
+    let base_work_dir = pull_result.config_blob.work_dir();
+    let (temp_layer, temp_local_layer) =
+        build_layer(&build_info.copy_files, &base_layers, base_work_dir, &copy_sources, &build_context)?.unzip();
+    let target_config_blob = build_target_config_blob(build_info, &pull_result.config_blob, temp_layer.as_ref(), &build_cmds.format)?;
     let source_manifest = pull_result.manifest;
-    let source_manifest_raw = pull_result.manifest_raw;
     let target_config_blob_serialize = target_config_blob.serialize()?;
     info!("Build a new target manifest.");
     let target_manifest = build_target_manifest(source_manifest, &build_cmds.format, temp_local_layer, &target_config_blob_serialize)?;
-    match &build_cmds.target {
+    let connection = TargetConnection {
+        allow_insecure: build_cmds.target_allow_insecure,
+        auth: build_cmds.target_auth.as_ref(),
+        proxy: build_cmds.target_proxy.as_ref(),
+        hosts: &build_cmds.target_hosts,
+        conn_timeout: build_cmds.conn_timeout,
+        upload_chunk_size: upload_chunk_bytes(build_cmds.upload_chunk_size)?,
+        mount_source: source_info.registry_image(),
+    };
+    save_targets(
+        &build_cmds.target,
+        &build_cmds.format,
+        &connection,
+        target_manifest,
+        target_config_blob_serialize,
+        &home_dir,
+    )
+}
+
+/// '--upload-chunk-size' 的单位为MiB，转换为字节数
+pub fn upload_chunk_bytes(chunk_size: u64) -> Result<u64> {
+    chunk_size.checked_mul(1024 * 1024).ok_or_else(|| anyhow!("'--upload-chunk-size' is too large: {}", chunk_size))
+}
+
+/// 推送到镜像仓库的连接参数，'hosts' 中单独设置的host优先
+pub struct TargetConnection<'a> {
+    pub allow_insecure: bool,
+    pub auth: Option<&'a BaseAuth>,
+    pub proxy: Option<&'a ProxyInfo>,
+    pub hosts: &'a TargetHostArgs,
+    pub conn_timeout: u64,
+    /// 分块上传blob时每一块的字节数
+    pub upload_chunk_size: u64,
+    /// 从镜像仓库拉取的源镜像，推送到同一个仓库时跨仓库挂载blob
+    pub mount_source: Option<&'a ImageInfo>,
+}
+
+impl<'a> TargetConnection<'a> {
+    /// 推送到一个镜像仓库目标时使用的连接参数
+    fn registry_connection(&self, auth_host: Option<&str>, target: &ImageInfo) -> RegistryConnection<'a> {
+        let (host, repository) = (target.image_host.as_str(), target.image_name.as_str());
+        RegistryConnection {
+            use_https: !(self.allow_insecure || self.hosts.insecure(host)),
+            auth: target_auth(self, auth_host, host, repository),
+            proxy: self.hosts.proxy(host, repository).or(self.proxy).cloned(),
+            conn_timeout_second: self.conn_timeout,
+            upload_chunk_size: self.upload_chunk_size,
+        }
+    }
+}
+
+/// layer和config只构建一次，依次保存到所有目标，仓库中已经存在的blob不会重复上传
+pub fn save_targets(
+    targets: &[TargetType],
+    format: &TargetFormat,
+    connection: &TargetConnection,
+    target_manifest: Manifest,
+    target_config_blob_serialize: ConfigBlobSerialize,
+    home_dir: &HomeDir,
+) -> Result<()> {
+    let auth_host = targets
+        .iter()
+        .find_map(|target| match target {
+            TargetType::Registry(image) => Some(parse_target_image(image).map(|image_info| image_info.image_host)),
+            _ => None,
+        })
+        .transpose()?;
+    for target in targets {
+        info!("Saving image to target: {}", target);
+        save_target(
+            target,
+            format,
+            connection,
+            auth_host.as_deref(),
+            target_manifest.clone(),
+            target_config_blob_serialize.clone(),
+            home_dir,
+        )
+        .map_err(|err| anyhow!("save to target failed: {}\n{}", target, err))?;
+    }
+    Ok(())
+}
+
+fn save_target(
+    target: &TargetType,
+    format: &TargetFormat,
+    connection: &TargetConnection,
+    auth_host: Option<&str>,
+    target_manifest: Manifest,
+    target_config_blob_serialize: ConfigBlobSerialize,
+    home_dir: &HomeDir,
+) -> Result<()> {
+    match target {
         TargetType::Registry(image) => {
+            let target_image = parse_target_image(image)?;
             let registry_adapter = RegistryTargetAdapter::new(
                 image,
-                build_cmds.format.clone(),
-                !build_cmds.target_allow_insecure,
+                format.clone(),
                 target_manifest,
                 target_config_blob_serialize,
-                build_cmds.target_auth.as_ref(),
-                build_cmds.conn_timeout,
-                build_cmds.target_proxy.clone(),
-            )?;
+                connection.registry_connection(auth_host, &target_image),
+            )?
+            .mount_from(connection.mount_source);
             registry_adapter.upload()?
         }
-        TargetType::Tar(tar_arg) => {
-            let image_raw_name = source_info.image_info.image_raw_name.ok_or_else(|| anyhow!("must set a raw name"))?;
-            let adapter = TarTargetAdapter {
-                image_raw_name,
+        TargetType::DockerArchive(archive_arg) => {
+            let adapter = DockerArchiveTargetAdapter {
+                reference: archive_arg.reference.clone(),
+                target_manifest,
+                target_config_blob_serialize,
+                save_path: PathBuf::from(&archive_arg.path),
+                use_gzip: archive_arg.use_gzip,
+            };
+            adapter.save(home_dir)?;
+        }
+        TargetType::OciArchive(oci_archive_arg) => {
+            let adapter = OciArchiveTargetAdapter {
+                tag: oci_archive_arg.tag.clone(),
+                target_manifest,
+                target_config_blob_serialize,
+                save_path: PathBuf::from(&oci_archive_arg.path),
+            };
+            adapter.save(home_dir)?;
+        }
+        TargetType::OciDir(oci_dir_arg) => {
+            let adapter = OciDirTargetAdapter {
+                tag: oci_dir_arg.tag.clone(),
                 target_manifest,
-                manifest_raw: source_manifest_raw,
                 target_config_blob_serialize,
-                save_path: PathBuf::from(tar_arg.path.clone()),
-                use_gzip: tar_arg.usb_gzip,
+                save_path: PathBuf::from(&oci_dir_arg.path),
             };
-            adapter.save()?;
+            adapter.save(home_dir)?;
         }
     }
     Ok(())
 }
 
-/// 构建一个tar layer
-fn build_top_tar(copyfiles: &[CopyFile], home_dir: &HomeDir) -> Result<Option<PathBuf>> {
+/// 全局的 '--target-auth' 只用于第一个镜像仓库目标的host，避免把凭证发送到其他仓库
+fn target_auth<'a>(connection: &TargetConnection<'a>, auth_host: Option<&str>, host: &str, repository: &str) -> Option<&'a BaseAuth> {
+    if let Some(auth) = connection.hosts.auth(host, repository) {
+        return Some(auth);
+    }
+    let auth_host = auth_host.unwrap_or_default();
+    match connection.auth {
+        Some(auth) if registry_host(auth_host) == registry_host(host) => Some(auth),
+        Some(_) => {
+            warn!(
+                "'--target-auth' is only used for {}, use '--target-host-auth' to set the auth of {}",
+                auth_host, host
+            );
+            None
+        }
+        None => None,
+    }
+}
+
+/// 构建上下文默认为Dockerfile所在的目录
+fn build_context_dir(build_cmds: &BuildCmdArgs) -> Result<PathBuf> {
+    let context_dir = match (&build_cmds.context, &build_cmds.source) {
+        (Some(context), _) => PathBuf::from(context),
+        (None, SourceType::Dockerfile { path }) => match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+        (None, _) => PathBuf::from("."),
+    };
+    if !context_dir.is_dir() {
+        return Err(anyhow!("build context is not a directory: {}", context_dir.to_string_lossy()));
+    }
+    Ok(context_dir)
+}
+
+/// 获取已经拉取到本地的所有layer，从底层到顶层排列
+fn local_layers(manifest: &Manifest, home_dir: &HomeDir) -> Result<Vec<LocalLayer>> {
+    manifest
+        .layers()
+        .iter()
+        .map(|layer| {
+            let digest = RegDigest::new_with_digest(layer.digest.to_string());
+            home_dir.cache.blobs.local_layer(&digest).ok_or_else(|| anyhow!("layer not found in local: {}", layer.digest))
+        })
+        .collect()
+}
+
+/// 构建过程中共享的参数
+struct BuildContext<'a> {
+    build_cmds: &'a BuildCmdArgs,
+    source_info: &'a SourceInfo,
+    home_dir: &'a HomeDir,
+    remote_downloader: &'a RemoteFileDownloader,
+    compress_type: CompressType,
+    /// 构建上下文目录，COPY和ADD的本地源路径都相对于该目录
+    context_dir: PathBuf,
+    docker_ignore: DockerIgnore,
+}
+
+/// 拉取镜像并返回本地的layers和config blob
+fn pull_layers(mut image_info: ImageInfo, context: &BuildContext) -> Result<(Vec<LocalLayer>, ConfigBlobEnum)> {
+    let build_cmds = context.build_cmds;
+    add_library_namespace(&mut image_info);
+    // 只有和源镜像在同一个仓库时才使用源镜像的认证信息
+    let base_auth = build_cmds.source_auth.as_ref().filter(|_| image_info.image_host == context.source_info.image_info.image_host);
+    let reg_auth = RegAuthType::build_auth(image_info.image_host.clone(), base_auth);
+    let source_info = SourceInfo {
+        image_info,
+        platform: context.source_info.platform.clone(),
+        local: None,
+    };
+    let pull_result = pull_source(
+        &source_info,
+        reg_auth,
+        !build_cmds.allow_insecure,
+        build_cmds.conn_timeout,
+        build_cmds.source_proxy.clone(),
+    )?;
+    Ok((local_layers(&pull_result.manifest, context.home_dir)?, pull_result.config_blob))
+}
+
+/// 准备 COPY --from 需要的镜像和阶段的layers，每个镜像和阶段只处理一次。
+/// 阶段的layers为基础镜像的layers加上该阶段新构建的layer
+fn prepare_copy_sources(
+    copyfiles: &[CopyFile],
+    stages: &[StageInfo],
+    context: &BuildContext,
+    copy_sources: &mut HashMap<CopyFrom, Vec<LocalLayer>>,
+) -> Result<()> {
+    for from in copyfiles.iter().filter_map(|copyfile| copyfile.from.as_ref()) {
+        if copy_sources.contains_key(from) {
+            continue;
+        }
+        let layers = match from {
+            CopyFrom::Image(image) => {
+                info!("Pull image of 'COPY --from={}'", image);
+                let (image_info, _) = DockerfileAdapter::parse_from_str(&format!("FROM {}", image))?;
+                pull_layers(image_info, context)?.0
+            }
+            CopyFrom::Stage(index) => {
+                let stage = stages.get(*index).ok_or_else(|| anyhow!("stage not found: {}", index))?;
+                info!("Build stage: {}", stage.name.clone().unwrap_or_else(|| index.to_string()));
+                let (mut layers, config_blob) = pull_layers(stage.image_info.clone(), context)?;
+                let stage_build_info = DockerfileAdapter::apply_on_build(stage.build_info.clone(), config_blob.on_build())?
+                    .expand_base_envs(&config_blob.envs())?;
+                let copy_files = &stage_build_info.copy_files;
+                prepare_copy_sources(copy_files, stages, context, copy_sources)?;
+                if let Some((_, local_layer)) = build_layer(copy_files, &layers, config_blob.work_dir(), copy_sources, context)? {
+                    layers.push(local_layer);
+                }
+                layers
+            }
+        };
+        copy_sources.insert(from.clone(), layers);
+    }
+    Ok(())
+}
+
+/// 构建并压缩新的layer，保存到本地的blobs中
+fn build_layer(
+    copyfiles: &[CopyFile],
+    base_layers: &[LocalLayer],
+    base_work_dir: Option<&str>,
+    copy_sources: &HashMap<CopyFrom, Vec<LocalLayer>>,
+    context: &BuildContext,
+) -> Result<Option<(TempLayerInfo, LocalLayer)>> {
+    let home_dir = context.home_dir;
+    let compress_type = context.compress_type;
+    let tar_path = match build_top_tar(copyfiles, base_layers, base_work_dir, copy_sources, context)? {
+        Some(tar_path) => tar_path,
+        None => return Ok(None),
+    };
+    let temp_layer = compress_layer_file(&tar_path, home_dir, compress_type)?;
+    home_dir.cache.blobs.move_to_blob(
+        &temp_layer.compress_layer_path,
+        &temp_layer.compressed_tar_sha256,
+        &temp_layer.tar_sha256,
+    )?;
+    let local_layer = home_dir.cache.blobs.create_layer_config(&temp_layer.tar_sha256, &temp_layer.compressed_tar_sha256, compress_type)?;
+    Ok(Some((temp_layer, local_layer)))
+}
+
+/// 构建一个tar layer，base_layers 用于解析 --chown 中的用户名和组名，copy_sources 为 COPY --from 的镜像或阶段
+fn build_top_tar(
+    copyfiles: &[CopyFile],
+    base_layers: &[LocalLayer],
+    base_work_dir: Option<&str>,
+    copy_sources: &HashMap<CopyFrom, Vec<LocalLayer>>,
+    context: &BuildContext,
+) -> Result<Option<PathBuf>> {
     if copyfiles.is_empty() {
         return Ok(None);
     }
+    let home_dir = context.home_dir;
+    let mut layer_dirs = LayerDirs::new(base_layers);
     info!("Building new tar...");
     let tar_file_name = random::random_str(10) + ".tar";
     let tar_temp_file_path = home_dir.cache.temp_dir.join(tar_file_name);
     let tar_temp_file = File::create(tar_temp_file_path.as_path())?;
     let mut tar_builder = Builder::new(tar_temp_file);
     for copyfile in copyfiles {
-        for source_path_str in &copyfile.source_path {
-            let source_path = PathBuf::from(&source_path_str);
-            if !source_path.exists() {
-                return Err(anyhow!("path not found:{}", source_path_str));
+        let attribute = FileAttribute {
+            owner: copyfile.chown.as_deref().map(|chown| layer_dirs.resolve_owner(chown)).transpose()?,
+            mode: copyfile.chmod,
+        };
+        // 以 '/' 结尾或者在镜像中已经存在的目录才作为目录，否则文件会被重命名
+        let dest_path = container_abs_path(base_work_dir.unwrap_or("/"), &copyfile.dest_path);
+        let dest_relative_path = relative_path(Path::new(&dest_path))?;
+        if let Some(from) = &copyfile.from {
+            let image_layers = copy_sources.get(from).ok_or_else(|| anyhow!("copy source not prepared: {:?}", from))?;
+            if copyfile.source_path.len() > 1 && !dest_path.ends_with('/') {
+                return Err(anyhow!(
+                    "when using COPY with more than one source file, the destination must be a directory and end with a /"
+                ));
+            }
+            let dest_is_dir = dest_path.ends_with('/') || layer_dirs.is_dir(&dest_relative_path)?;
+            for source_path_str in &copyfile.source_path {
+                append_image_files(
+                    &mut tar_builder,
+                    image_layers,
+                    source_path_str,
+                    &dest_relative_path,
+                    dest_is_dir,
+                    &attribute,
+                )?;
             }
-            let dest_path = if copyfile.dest_path.ends_with('/') {
-                &copyfile.dest_path[1..]
+            layer_dirs.add_dir(&dest_relative_path);
+            continue;
+        }
+        let mut source_files = Vec::new();
+        for source_path_str in &copyfile.source_path {
+            if is_remote_url(source_path_str) {
+                source_files.push(SourceFile::Remote(source_path_str));
             } else {
-                &copyfile.dest_path
+                source_files.extend(context_sources(context, source_path_str)?);
+            }
+        }
+        if source_files.len() > 1 && !dest_path.ends_with('/') {
+            return Err(anyhow!(
+                "when using COPY with more than one source file, the destination must be a directory and end with a /"
+            ));
+        }
+        for source_file in source_files {
+            let (source_relative_path, source_path) = match source_file {
+                SourceFile::Context { relative_path, full_path } => (relative_path, full_path),
+                SourceFile::Remote(source_path_str) => {
+                    let local_path = context.remote_downloader.download(source_path_str, copyfile.checksum.as_deref(), &home_dir.cache)?;
+                    let dest_file_path = if dest_path.ends_with('/') {
+                        dest_relative_path.join(remote_file_name(source_path_str)?)
+                    } else {
+                        dest_relative_path.clone()
+                    };
+                    append_remote_file(&mut tar_builder, &local_path, &dest_file_path, &attribute)?;
+                    continue;
+                }
             };
             if source_path.is_file() {
-                let file_name = source_path.file_name().ok_or_else(|| anyhow!("error file name"))?.to_string_lossy();
-                let dest_file_path = PathBuf::from(dest_path).join(file_name.to_string()).to_string_lossy().to_string();
-                let mut sourcefile = File::open(source_path)?;
-                tar_builder.append_file(dest_file_path, &mut sourcefile)?;
+                if copyfile.extract_archive {
+                    if let Some(archive) = compress::archive_reader(&source_path)? {
+                        append_archive(&mut tar_builder, archive, &dest_relative_path, &attribute)?;
+                        layer_dirs.add_dir(&dest_relative_path);
+                        continue;
+                    }
+                }
+                let dest_file_path = if dest_path.ends_with('/') || layer_dirs.is_dir(&dest_relative_path)? {
+                    dest_relative_path.join(source_path.file_name().ok_or_else(|| anyhow!("error file name"))?)
+                } else {
+                    dest_relative_path.clone()
+                };
+                if dest_file_path.as_os_str().is_empty() {
+                    return Err(anyhow!("error copy destination: {}", dest_path));
+                }
+                let mut sourcefile = File::open(&source_path)?;
+                let mut header = Header::new_gnu();
+                header.set_metadata(&sourcefile.metadata()?);
+                attribute.apply(&mut header);
+                tar_builder.append_data(&mut header, &dest_file_path, &mut sourcefile)?;
+                if let Some(parent) = dest_file_path.parent() {
+                    layer_dirs.add_dir(parent);
+                }
             } else if source_path.is_dir() {
-                tar_builder.append_dir(dest_path, source_path_str)?;
+                // 复制目录中的内容，而不是目录本身
+                if !dest_relative_path.as_os_str().is_empty() {
+                    let mut header = Header::new_gnu();
+                    header.set_metadata(&source_path.metadata()?);
+                    attribute.apply(&mut header);
+                    tar_builder.append_data(&mut header, &dest_relative_path, io::empty())?;
+                }
+                append_context_dir(&mut tar_builder, context, &source_relative_path, &dest_relative_path, &attribute)?;
+                layer_dirs.add_dir(&dest_relative_path);
             } else {
                 return Err(anyhow!("copy only support file and dir".to_string()));
             }
@@ -208,8 +597,250 @@ fn build_top_tar(copyfiles: &[CopyFile], home_dir: &HomeDir) -> Result<Option<Pa
     Ok(Some(tar_temp_file_path))
 }
 
+/// 源路径都相对于构建上下文，返回相对路径和完整路径，超出构建上下文时返回错误
+fn context_path(context_dir: &Path, source_path: &str) -> Result<(PathBuf, PathBuf)> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(source_path).components() {
+        if component == Component::ParentDir && !relative.pop() {
+            return Err(anyhow!("path is outside of the build context: {}", source_path));
+        }
+        if let Component::Normal(name) = component {
+            relative.push(name);
+        }
+    }
+    let full_path = context_dir.join(&relative);
+    // 通过软链接指向构建上下文之外的路径也不允许
+    if full_path.exists() && !full_path.canonicalize()?.starts_with(context_dir.canonicalize()?) {
+        return Err(anyhow!("path is outside of the build context: {}", source_path));
+    }
+    Ok((relative, full_path))
+}
+
+/// 展开通配符之后的源文件
+enum SourceFile<'a> {
+    Remote(&'a str),
+    Context { relative_path: PathBuf, full_path: PathBuf },
+}
+
+/// 解析构建上下文中的源路径，展开其中的通配符，.dockerignore 中忽略的文件不会被匹配
+fn context_sources<'a>(context: &BuildContext, source_path: &str) -> Result<Vec<SourceFile<'a>>> {
+    let (pattern, full_path) = context_path(&context.context_dir, source_path)?;
+    if !has_magic(source_path) {
+        if !full_path.exists() || context.docker_ignore.is_ignored(&pattern) {
+            return Err(anyhow!(
+                "path not found in build context or excluded by .dockerignore: {}",
+                source_path
+            ));
+        }
+        return Ok(vec![SourceFile::Context {
+            relative_path: pattern,
+            full_path,
+        }]);
+    }
+    // 按目录逐级匹配，和Go的filepath.Glob一样 '*' 不匹配 '/'
+    let mut matched_paths = vec![PathBuf::new()];
+    for segment in pattern.iter().map(|segment| segment.to_string_lossy()) {
+        let mut next_paths = Vec::new();
+        for matched_path in matched_paths {
+            let dir = context.context_dir.join(&matched_path);
+            if !has_magic(&segment) {
+                if dir.join(segment.as_ref()).symlink_metadata().is_ok() {
+                    next_paths.push(matched_path.join(segment.as_ref()));
+                }
+                continue;
+            }
+            if !dir.is_dir() {
+                continue;
+            }
+            let mut names = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            for name in names {
+                if match_segment(&segment, &name.to_string_lossy()) {
+                    next_paths.push(matched_path.join(name));
+                }
+            }
+        }
+        matched_paths = next_paths;
+    }
+    let mut source_files = Vec::new();
+    for matched_path in matched_paths.into_iter().filter(|path| !context.docker_ignore.is_ignored(path)) {
+        let (relative_path, full_path) = context_path(&context.context_dir, &matched_path.to_string_lossy())?;
+        source_files.push(SourceFile::Context { relative_path, full_path });
+    }
+    if source_files.is_empty() {
+        return Err(anyhow!("no source files were specified: {}", source_path));
+    }
+    Ok(source_files)
+}
+
+/// 递归添加构建上下文中目录的内容，跳过 .dockerignore 中忽略的文件，软链接保持为软链接
+fn append_context_dir<W: Write>(
+    tar_builder: &mut Builder<W>,
+    context: &BuildContext,
+    dir_relative_path: &Path,
+    dest_dir: &Path,
+    attribute: &FileAttribute,
+) -> Result<()> {
+    let mut entries = fs::read_dir(context.context_dir.join(dir_relative_path))?.collect::<io::Result<Vec<DirEntry>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let relative_path = dir_relative_path.join(entry.file_name());
+        let dest_path = dest_dir.join(entry.file_name());
+        let metadata = entry.path().symlink_metadata()?;
+        let ignored = context.docker_ignore.is_ignored(&relative_path);
+        let mut header = Header::new_gnu();
+        header.set_metadata(&metadata);
+        attribute.apply(&mut header);
+        if metadata.is_dir() {
+            if !ignored {
+                tar_builder.append_data(&mut header, &dest_path, io::empty())?;
+            }
+            // 被忽略的目录中可能有 '!' 重新包含的文件
+            if !ignored || context.docker_ignore.has_exceptions() {
+                append_context_dir(tar_builder, context, &relative_path, &dest_path, attribute)?;
+            }
+        } else if ignored {
+            continue;
+        } else if metadata.file_type().is_symlink() {
+            tar_builder.append_link(&mut header, &dest_path, fs::read_link(entry.path())?)?;
+        } else if metadata.is_file() {
+            tar_builder.append_data(&mut header, &dest_path, File::open(entry.path())?)?;
+        }
+    }
+    Ok(())
+}
+
+/// COPY/ADD 的 --chown 和 --chmod，写入tar时覆盖header中的值
+struct FileAttribute {
+    owner: Option<(u64, u64)>,
+    mode: Option<u32>,
+}
+
+impl FileAttribute {
+    fn apply(&self, header: &mut Header) {
+        if let Some((uid, gid)) = self.owner {
+            header.set_uid(uid);
+            header.set_gid(gid);
+        }
+        // 链接的权限没有意义，不做修改
+        if let Some(mode) = self.mode {
+            if !header.entry_type().is_symlink() && !header.entry_type().is_hard_link() {
+                header.set_mode(mode);
+            }
+        }
+    }
+}
+
+/// 将tar归档中的所有文件解压到layer的目标目录中
+fn append_archive<W: Write>(
+    tar_builder: &mut Builder<W>,
+    archive_reader: Box<dyn Read>,
+    dest_dir: &Path,
+    attribute: &FileAttribute,
+) -> Result<()> {
+    let mut archive = Archive::new(archive_reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let mut header = entry.header().clone();
+        let entry_type = header.entry_type();
+        if entry_type.is_pax_global_extensions() || entry_type.is_pax_local_extensions() {
+            continue;
+        }
+        let entry_path = dest_dir.join(relative_path(&entry.path()?)?);
+        attribute.apply(&mut header);
+        match entry_type {
+            EntryType::Link => {
+                let link_name = entry.link_name()?.ok_or_else(|| anyhow!("hard link without target"))?;
+                tar_builder.append_link(&mut header, &entry_path, dest_dir.join(relative_path(&link_name)?))?
+            }
+            EntryType::Symlink => {
+                let link_name = entry.link_name()?.ok_or_else(|| anyhow!("symlink without target"))?.into_owned();
+                tar_builder.append_link(&mut header, &entry_path, link_name)?
+            }
+            _ => tar_builder.append_data(&mut header, &entry_path, &mut entry)?,
+        }
+    }
+    Ok(())
+}
+
+/// 从镜像的layers中复制文件，源路径为目录时复制目录中的内容，为文件时目标以 '/' 结尾则放入该目录中
+fn append_image_files<W: Write>(
+    tar_builder: &mut Builder<W>,
+    image_layers: &[LocalLayer],
+    source_path: &str,
+    dest_dir: &Path,
+    dest_is_dir: bool,
+    attribute: &FileAttribute,
+) -> Result<()> {
+    let source_dir = relative_path(Path::new(source_path))?;
+    let found = walk_layer_files(image_layers, source_path, |entry_relative_path, entry| {
+        let mut header = entry.header().clone();
+        let entry_type = header.entry_type();
+        let entry_path = if !entry_relative_path.as_os_str().is_empty() {
+            dest_dir.join(entry_relative_path)
+        } else if !entry_type.is_dir() && dest_is_dir {
+            dest_dir.join(source_dir.file_name().ok_or_else(|| anyhow!("error source path: {}", source_path))?)
+        } else {
+            dest_dir.to_path_buf()
+        };
+        // 复制到根目录时跳过根目录本身
+        if entry_path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        attribute.apply(&mut header);
+        match entry_type {
+            EntryType::Link => {
+                let link_name = relative_path(&entry.link_name()?.ok_or_else(|| anyhow!("hard link without target"))?)?;
+                let link_relative_path = link_name
+                    .strip_prefix(&source_dir)
+                    .map_err(|_| anyhow!("hard link target is outside of '{}': {:?}", source_path, link_name))?;
+                tar_builder.append_link(&mut header, &entry_path, dest_dir.join(link_relative_path))?
+            }
+            EntryType::Symlink => {
+                let link_name = entry.link_name()?.ok_or_else(|| anyhow!("symlink without target"))?.into_owned();
+                tar_builder.append_link(&mut header, &entry_path, link_name)?
+            }
+            _ => tar_builder.append_data(&mut header, &entry_path, entry)?,
+        }
+        Ok(())
+    })?;
+    if !found {
+        return Err(anyhow!("path not found in image: {}", source_path));
+    }
+    Ok(())
+}
+
+/// 添加ADD下载的远程文件，和Docker一样默认权限为600
+fn append_remote_file<W: Write>(
+    tar_builder: &mut Builder<W>,
+    local_path: &Path,
+    dest_file_path: &Path,
+    attribute: &FileAttribute,
+) -> Result<()> {
+    let mut file = File::open(local_path)?;
+    let mut header = Header::new_gnu();
+    header.set_metadata(&file.metadata()?);
+    header.set_mode(0o600);
+    attribute.apply(&mut header);
+    tar_builder.append_data(&mut header, relative_path(dest_file_path)?, &mut file)?;
+    Ok(())
+}
+
+/// 去掉路径中的根目录和 '.'，包含 '..' 的路径可能写到目标目录之外，直接返回错误
+fn relative_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::ParentDir => return Err(anyhow!("path contains '..': {}", path.to_string_lossy())),
+            _ => {}
+        }
+    }
+    Ok(relative)
+}
+
 /// 压缩tar layer文件为指定格式
-fn compress_layer_file(tar_file_path: &Path, home_dir: &HomeDir, compress_type: CompressType) -> Result<TempLayerInfo> {
+pub fn compress_layer_file(tar_file_path: &Path, home_dir: &HomeDir, compress_type: CompressType) -> Result<TempLayerInfo> {
     let tar_file = File::open(tar_file_path)?;
     let mut sha256_reader = Sha256Reader::new(tar_file);
     let compress_file_name = random::random_str(20) + ".compress";
@@ -234,7 +865,7 @@ pub fn build_target_config_blob(
     source_config_blob: &ConfigBlobEnum,
     temp_layer_opt: Option<&TempLayerInfo>,
     target_format: &TargetFormat,
-) -> ConfigBlobEnum {
+) -> Result<ConfigBlobEnum> {
     // TODO change to diff config type
     let mut target_config_blob = match target_format {
         TargetFormat::Docker => source_config_blob.clone(),
@@ -247,19 +878,52 @@ pub fn build_target_config_blob(
     }
     target_config_blob.add_labels(build_info.labels);
     target_config_blob.add_envs(build_info.envs);
+    // ENTRYPOINT会重置CMD，所以必须在CMD之前设置
+    if let Some(entrypoints) = build_info.entrypoint {
+        target_config_blob.overwrite_entrypoint(entrypoints)
+    }
     if let Some(cmds) = build_info.cmd {
         target_config_blob.overwrite_cmd(cmds)
     }
     if let Some(port_exposes) = build_info.ports {
         target_config_blob.add_ports(port_exposes);
     }
+    if let Some(volumes) = build_info.volumes {
+        target_config_blob.add_volumes(volumes);
+    }
+    if let Some(healthcheck) = build_info.healthcheck {
+        target_config_blob.overwrite_healthcheck(healthcheck);
+    }
+    if let Some(stop_signal) = build_info.stop_signal {
+        target_config_blob.overwrite_stop_signal(stop_signal);
+    }
+    if let Some(shell) = build_info.shell {
+        target_config_blob.overwrite_shell(shell);
+    }
+    // 基础镜像的ONBUILD触发器已经执行，只保留当前Dockerfile中的ONBUILD
+    target_config_blob.overwrite_on_build(if build_info.on_build.is_empty() {
+        None
+    } else {
+        Some(build_info.on_build)
+    });
     if let Some(work_dir) = build_info.workdir {
+        // 相对路径的WORKDIR基于基础镜像的WORKDIR
+        let work_dir = if work_dir.starts_with('/') {
+            work_dir
+        } else {
+            let work_dir = container_abs_path(source_config_blob.work_dir().unwrap_or("/"), &work_dir);
+            if work_dir.len() > 1 {
+                work_dir.trim_end_matches('/').to_string()
+            } else {
+                work_dir
+            }
+        };
         target_config_blob.overwrite_work_dir(work_dir);
     }
     if let Some(user) = build_info.user {
         target_config_blob.overwrite_user(user);
     }
-    target_config_blob
+    Ok(target_config_blob)
 }
 
 pub fn build_target_manifest(
@@ -278,3 +942,165 @@ pub fn build_target_manifest(
     }
     Ok(target_manifest)
 }
+
+#[test]
+fn base_env_expand_works() -> Result<()> {
+    use crate::container::image::docker::DockerConfigBlob;
+
+    let base_config = r#"{"architecture":"amd64","os":"linux","config":{"Env":["PATH=/usr/local/bin:/usr/bin","HOME=/root"]},"rootfs":{"type":"layers","diff_ids":[]},"history":[]}"#;
+    let base_config_blob = ConfigBlobEnum::DockerV2S2(serde_json::from_str::<DockerConfigBlob>(base_config)?);
+    let dockerfile = "FROM ubuntu\nARG VERSION=1.0\nENV PATH=/x:$PATH APP_HOME=${HOME}/app\nENV PATH=/y:$PATH NAME=app-$VERSION PRICE=\\$5 EMPTY=$MISSING\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    let build_info = build_info.expand_base_envs(&base_config_blob.envs())?;
+    let target_config_blob = build_target_config_blob(build_info, &base_config_blob, None, &TargetFormat::Docker)?;
+    let envs = target_config_blob.envs();
+    assert_eq!(envs.get("PATH").map(String::as_str), Some("/y:/x:/usr/local/bin:/usr/bin"));
+    assert_eq!(envs.get("APP_HOME").map(String::as_str), Some("/root/app"));
+    assert_eq!(envs.get("NAME").map(String::as_str), Some("app-1.0"));
+    assert_eq!(envs.get("PRICE").map(String::as_str), Some("$5"));
+    assert_eq!(envs.get("EMPTY").map(String::as_str), Some(""));
+    // 同名的变量只保留一个
+    let ConfigBlobEnum::DockerV2S2(docker) = target_config_blob else {
+        unreachable!()
+    };
+    assert_eq!(
+        docker.config.env.unwrap_or_default().iter().filter(|env| env.starts_with("PATH=")).count(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn base_env_in_instructions_works() -> Result<()> {
+    use crate::container::image::docker::DockerConfigBlob;
+
+    let base_config = r#"{"architecture":"amd64","os":"linux","config":{"Env":["HOME=/root","PORT=8080"],"WorkingDir":"/srv"},"rootfs":{"type":"layers","diff_ids":[]},"history":[]}"#;
+    let base_config_blob = ConfigBlobEnum::DockerV2S2(serde_json::from_str::<DockerConfigBlob>(base_config)?);
+    let dockerfile = "FROM ubuntu\nENV APP_HOME=$HOME/app\nWORKDIR /tmp\nWORKDIR $APP_HOME\nCOPY app.jar $APP_HOME/lib/\nCOPY run.sh bin/\n\
+                      COPY --chown=$USER_ID app.conf ${CONF_DIR:-/etc}/app.conf\nLABEL home=$HOME\nUSER ${USER_ID:-app}\nEXPOSE $PORT 9090/udp\n";
+    let (_, build_info) = DockerfileAdapter::parse_from_str(dockerfile)?;
+    let build_info = build_info.expand_base_envs(&base_config_blob.envs())?;
+    let dest_paths = build_info.copy_files.iter().map(|copy_file| copy_file.dest_path.as_str()).collect::<Vec<&str>>();
+    assert_eq!(dest_paths, ["/root/app/lib/", "/root/app/bin/", "/etc/app.conf"]);
+    assert_eq!(build_info.copy_files[2].chown.as_deref(), Some(""));
+    assert_eq!(build_info.labels.get("home").map(String::as_str), Some("/root"));
+    let target_config_blob = build_target_config_blob(build_info, &base_config_blob, None, &TargetFormat::Docker)?;
+    let ConfigBlobEnum::DockerV2S2(docker) = target_config_blob else {
+        unreachable!()
+    };
+    assert_eq!(docker.config.working_dir.as_deref(), Some("/root/app"));
+    assert_eq!(docker.config.user.as_deref(), Some("app"));
+    let mut ports = docker.config.exposed_ports.unwrap_or_default().into_keys().collect::<Vec<String>>();
+    ports.sort();
+    assert_eq!(ports, ["8080/tcp", "9090/udp"]);
+    Ok(())
+}
+
+#[test]
+fn cmd_only_options_works() -> Result<()> {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct TestCmd {
+        #[clap(flatten)]
+        build_cmds: BuildCmdArgs,
+    }
+    let args = "ocipack --source dockerfile:/not/exists/Dockerfile --target registry:example.com/app:1.0 --env A=B --workdir /app";
+    let test_cmd = TestCmd::try_parse_from(args.split_whitespace())?;
+    let err = build_source_info(&test_cmd.build_cmds).err().expect("cmd options with dockerfile source");
+    assert_eq!(err.to_string(), "'--env', '--workdir' can only be used with the 'cmd:' source");
+    let args = "ocipack --source registry:alpine:3.18 --target registry:example.com/app:1.0 --from alpine:3.18";
+    let test_cmd = TestCmd::try_parse_from(args.split_whitespace())?;
+    assert!(build_source_info(&test_cmd.build_cmds).is_err());
+    let args = "ocipack --source registry:alpine:3.18 --target registry:example.com/app:1.0";
+    let test_cmd = TestCmd::try_parse_from(args.split_whitespace())?;
+    assert!(build_source_info(&test_cmd.build_cmds).is_ok());
+    Ok(())
+}
+
+#[test]
+fn upload_chunk_bytes_works() -> Result<()> {
+    assert_eq!(upload_chunk_bytes(64)?, 64 * 1024 * 1024);
+    assert_eq!(upload_chunk_bytes(0)?, 0);
+    assert!(upload_chunk_bytes(u64::MAX / 1024).is_err());
+    Ok(())
+}
+
+#[test]
+fn target_auth_works() -> Result<()> {
+    use crate::config::cmd::HostArg;
+
+    let hosts = TargetHostArgs {
+        target_host_auth: vec![
+            "ghcr.io=ghcr:token".parse::<HostArg<BaseAuth>>()?,
+            "my.harbor.com/project-a=robot-a:token".parse()?,
+            "my.harbor.com/project-b/=robot-b:token".parse()?,
+        ],
+        ..Default::default()
+    };
+    let global_auth = "name:pass".parse::<BaseAuth>()?;
+    let connection = TargetConnection {
+        allow_insecure: false,
+        auth: Some(&global_auth),
+        proxy: None,
+        hosts: &hosts,
+        conn_timeout: 10,
+        upload_chunk_size: 0,
+        mount_source: None,
+    };
+    let username = |auth_host: Option<&str>, host: &str, repository: &str| {
+        target_auth(&connection, auth_host, host, repository).map(|auth| auth.username.clone())
+    };
+    assert_eq!(
+        username(Some("my.harbor.com"), "my.harbor.com", "jelipo/app"),
+        Some("name".to_string())
+    );
+    assert_eq!(username(Some("my.harbor.com"), "ghcr.io", "jelipo/app"), Some("ghcr".to_string()));
+    // 同一个仓库中的不同项目使用各自的凭证
+    assert_eq!(
+        username(Some("my.harbor.com"), "my.harbor.com", "project-a/app"),
+        Some("robot-a".to_string())
+    );
+    assert_eq!(
+        username(Some("my.harbor.com"), "my.harbor.com", "project-b/app"),
+        Some("robot-b".to_string())
+    );
+    assert_eq!(
+        username(Some("my.harbor.com"), "my.harbor.com", "project-ab/app"),
+        Some("name".to_string())
+    );
+    // 全局的凭证不会发送到其他仓库
+    assert_eq!(username(Some("my.harbor.com"), "other.io", "jelipo/app"), None);
+    assert_eq!(
+        username(Some("docker.io"), "registry-1.docker.io", "library/app"),
+        Some("name".to_string())
+    );
+    Ok(())
+}
+
+#[test]
+fn relative_path_works() -> Result<()> {
+    assert_eq!(relative_path(Path::new("/app/./bin/"))?, PathBuf::from("app/bin"));
+    let err = relative_path(Path::new("app/../../etc/passwd")).unwrap_err();
+    assert!(err.to_string().contains("app/../../etc/passwd"));
+    // ADD 解压的压缩包中包含 '..' 的文件
+    let mut header = Header::new_gnu();
+    let name = b"../evil.sh";
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.set_size(4);
+    header.set_mode(0o644);
+    header.set_cksum();
+    let mut archive_builder = Builder::new(Vec::new());
+    archive_builder.append(&header, &b"evil"[..])?;
+    let archive = archive_builder.into_inner()?;
+    let attribute = FileAttribute { owner: None, mode: None };
+    let err = append_archive(
+        &mut Builder::new(Vec::new()),
+        Box::new(io::Cursor::new(archive)),
+        Path::new("app"),
+        &attribute,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("../evil.sh"));
+    Ok(())
+}
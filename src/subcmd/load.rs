@@ -0,0 +1,315 @@
+use std::fs;
+use std::fs::{read_to_string, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use tar::Archive;
+
+use crate::adapter::tar::TarManifestJson;
+use crate::container::home::{HomeDir, LocalLayer};
+use crate::container::image::docker::{DockerConfigBlob, DockerManifest};
+use crate::container::image::oci::{OciConfigBlob, OciDescriptor, OciLayoutIndex};
+use crate::container::manifest::{CommonManifestConfig, CommonManifestLayer, Manifest, ManifestList, ManifestResponseEnum, Type};
+use crate::container::{CompressType, ConfigBlobEnum, Platform, RegContentType, RegDigest};
+use crate::subcmd::build::compress_layer_file;
+use crate::subcmd::pull::PullResult;
+use crate::util::compress::{detect_compress_type, uncompress, uncompress_reader};
+use crate::util::random;
+use crate::util::sha::{bytes_sha256, Sha256Writer};
+
+/// 读取 docker save 生成的tar文件，将layers压缩后导入到本地缓存中，和从镜像仓库拉取的结果相同
+pub fn load_docker_archive(archive_path: &Path, home_dir: &HomeDir) -> Result<PullResult> {
+    info!("Load source image from docker archive: {:?}", archive_path);
+    let unpack_dir = tempfile::Builder::new().prefix("archive").tempdir_in(&home_dir.cache.temp_dir)?;
+    let archive_reader = uncompress_reader(detect_compress_type(archive_path)?, File::open(archive_path)?)?;
+    Archive::new(archive_reader).unpack(unpack_dir.path())?;
+    let manifest_json =
+        read_to_string(unpack_dir.path().join("manifest.json")).map_err(|_| anyhow!("manifest.json not found in docker archive"))?;
+    let images = serde_json::from_str::<Vec<TarManifestJson>>(&manifest_json)?;
+    let image = images.first().ok_or_else(|| anyhow!("no image in docker archive"))?;
+    if images.len() > 1 {
+        warn!(
+            "docker archive contains {} images, use the first one: {:?}",
+            images.len(),
+            image.repo_tags
+        );
+    }
+    let config_raw = read_to_string(archive_entry_path(unpack_dir.path(), &image.config)?)?;
+    let config_blob = serde_json::from_str::<DockerConfigBlob>(&config_raw)?;
+    let diff_ids = &config_blob.rootfs.diff_ids;
+    if diff_ids.len() != image.layers.len() {
+        return Err(anyhow!("layers of docker archive do not match the diff_ids of config"));
+    }
+    let layers = image
+        .layers
+        .iter()
+        .zip(diff_ids)
+        .map(|(layer_path, diff_id)| {
+            let local_layer = import_archive_layer(&archive_entry_path(unpack_dir.path(), layer_path)?, diff_id, home_dir)?;
+            Ok(CommonManifestLayer {
+                media_type: RegContentType::DOCKER_LAYER_TGZ.val().to_string(),
+                size: local_layer.layer_file_path.metadata()?.len(),
+                digest: format!("sha256:{}", local_layer.manifest_sha),
+            })
+        })
+        .collect::<Result<Vec<CommonManifestLayer>>>()?;
+    let manifest = DockerManifest {
+        schema_version: 2,
+        media_type: RegContentType::DOCKER_MANIFEST.val().to_string(),
+        config: CommonManifestConfig {
+            media_type: RegContentType::DOCKER_CONTAINER_IMAGE.val().to_string(),
+            size: config_raw.len() as u64,
+            digest: format!("sha256:{}", bytes_sha256(config_raw.as_bytes())),
+        },
+        layers,
+    };
+    Ok(PullResult {
+        config_blob: ConfigBlobEnum::DockerV2S2(config_blob),
+        manifest: Manifest::DockerV2S2(manifest),
+    })
+}
+
+/// 读取OCI image layout目录，按tag和平台选择镜像，将blobs导入到本地缓存中
+pub fn load_oci_layout(layout_dir: &Path, tag: Option<&str>, platform: Option<&Platform>, home_dir: &HomeDir) -> Result<PullResult> {
+    info!("Load source image from OCI layout: {:?}", layout_dir);
+    if !layout_dir.join("oci-layout").is_file() {
+        return Err(anyhow!("not an OCI image layout: {:?}", layout_dir));
+    }
+    let index_json = read_to_string(layout_dir.join("index.json"))?;
+    let index = serde_json::from_str::<OciLayoutIndex>(&index_json)?;
+    let descriptor = select_descriptor(&index, &index_json, tag, platform)?;
+    let manifest = read_layout_manifest(layout_dir, &descriptor.digest, Some(&descriptor.media_type), platform)?;
+    for layer in manifest.layers() {
+        let digest = RegDigest::new_with_digest(layer.digest.to_string());
+        if home_dir.cache.blobs.local_layer(&digest).is_some() {
+            continue;
+        }
+        let compress_type = RegContentType::compress_type(layer.media_type)?;
+        import_layout_layer(&layout_blob_path(layout_dir, layer.digest)?, &digest, compress_type, home_dir)?;
+    }
+    let config_raw = read_layout_blob(layout_dir, manifest.config_digest())?;
+    let config_blob = match &manifest {
+        Manifest::OciV1(_) => ConfigBlobEnum::OciV1(serde_json::from_slice::<OciConfigBlob>(&config_raw)?),
+        Manifest::DockerV2S2(_) => ConfigBlobEnum::DockerV2S2(serde_json::from_slice::<DockerConfigBlob>(&config_raw)?),
+    };
+    Ok(PullResult { config_blob, manifest })
+}
+
+/// 有tag时按 'org.opencontainers.image.ref.name' 查找，只有一个镜像时直接使用，否则把 index.json 当作多平台的镜像选择
+fn select_descriptor<'a>(
+    index: &'a OciLayoutIndex,
+    index_json: &str,
+    tag: Option<&str>,
+    platform: Option<&Platform>,
+) -> Result<&'a OciDescriptor> {
+    if let Some(tag) = tag {
+        return index
+            .manifests
+            .iter()
+            .find(|descriptor| descriptor.ref_name() == Some(tag))
+            .ok_or_else(|| anyhow!("tag '{}' not found in OCI layout", tag));
+    }
+    if let [descriptor] = index.manifests.as_slice() {
+        return Ok(descriptor);
+    }
+    let manifest_list =
+        ManifestList::from(index_json, Type::Oci).map_err(|_| anyhow!("OCI layout contains multiple images, please set a tag"))?;
+    let platform = platform.cloned().unwrap_or_default();
+    let digest = manifest_list.find_platform_digest(&platform).ok_or_else(|| anyhow!("platform '{}' not found in OCI layout", platform))?;
+    index
+        .manifests
+        .iter()
+        .find(|descriptor| descriptor.digest == digest)
+        .ok_or_else(|| anyhow!("manifest not found in OCI layout: {}", digest))
+}
+
+/// 读取manifest，为多平台的index时按平台选择
+fn read_layout_manifest(layout_dir: &Path, digest: &str, media_type: Option<&str>, platform: Option<&Platform>) -> Result<Manifest> {
+    let body = String::from_utf8(read_layout_blob(layout_dir, digest)?)?;
+    let media_type = match media_type {
+        Some(media_type) => media_type.to_string(),
+        None => serde_json::from_str::<serde_json::Value>(&body)?["mediaType"]
+            .as_str()
+            .unwrap_or(RegContentType::OCI_MANIFEST.val())
+            .to_string(),
+    };
+    match ManifestResponseEnum::from(media_type, &body)? {
+        ManifestResponseEnum::Manifest(manifest) => Ok(manifest),
+        ManifestResponseEnum::ManifestList(manifest_list) => {
+            let platform_or_default = platform.cloned().unwrap_or_default();
+            let digest = manifest_list
+                .find_platform_digest(&platform_or_default)
+                .ok_or_else(|| anyhow!("platform '{}' not found in OCI layout", platform_or_default))?;
+            read_layout_manifest(layout_dir, &digest, None, platform)
+        }
+    }
+}
+
+fn layout_blob_path(layout_dir: &Path, digest: &str) -> Result<PathBuf> {
+    match digest.split_once(':') {
+        Some((algorithm, encoded)) if !encoded.is_empty() && encoded.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            Ok(layout_dir.join("blobs").join(algorithm).join(encoded))
+        }
+        _ => Err(anyhow!("error digest: {}", digest)),
+    }
+}
+
+/// 读取blob并校验sha256
+fn read_layout_blob(layout_dir: &Path, digest: &str) -> Result<Vec<u8>> {
+    let blob =
+        fs::read(layout_blob_path(layout_dir, digest)?).map_err(|err| anyhow!("blob not found in OCI layout {}: {}", digest, err))?;
+    if digest.starts_with("sha256:") && format!("sha256:{}", bytes_sha256(&blob)) != digest {
+        return Err(anyhow!("blob does not match its digest: {}", digest));
+    }
+    Ok(blob)
+}
+
+/// 复制已经压缩的layer到缓存中，同时校验digest并计算diff_id
+fn import_layout_layer(blob_path: &Path, digest: &RegDigest, compress_type: CompressType, home_dir: &HomeDir) -> Result<LocalLayer> {
+    let temp_path = home_dir.cache.temp_dir.join(random::random_str(20) + ".layer");
+    let mut sha256_writer = Sha256Writer::new(File::create(&temp_path)?);
+    io::copy(&mut File::open(blob_path)?, &mut sha256_writer)?;
+    if sha256_writer.sha256()? != digest.sha256 {
+        fs::remove_file(&temp_path)?;
+        return Err(anyhow!("layer does not match its digest: {}", digest.digest));
+    }
+    let mut diff_sha256 = Sha256::new();
+    uncompress(compress_type, &mut File::open(&temp_path)?, &mut diff_sha256)?;
+    let diff_layer_sha = hex::encode(&diff_sha256.finalize()[..]);
+    let blobs = &home_dir.cache.blobs;
+    blobs.move_to_blob(&temp_path, &digest.sha256, &diff_layer_sha)?;
+    blobs.create_layer_config(&diff_layer_sha, &digest.sha256, compress_type)
+}
+
+/// 归档中的文件路径不能跳出解压目录
+fn archive_entry_path(unpack_dir: &Path, name: &str) -> Result<PathBuf> {
+    if Path::new(name).components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(anyhow!("invalid path in archive: {}", name));
+    }
+    Ok(unpack_dir.join(name))
+}
+
+/// docker archive中的layer一般没有压缩，使用gzip压缩后导入缓存，并校验diff_id
+fn import_archive_layer(layer_path: &Path, diff_id: &str, home_dir: &HomeDir) -> Result<LocalLayer> {
+    let tar_path = match detect_compress_type(layer_path)? {
+        CompressType::Tar => layer_path.to_path_buf(),
+        compress_type => {
+            let tar_path = layer_path.with_extension("uncompressed");
+            uncompress(compress_type, &mut File::open(layer_path)?, &mut File::create(&tar_path)?)?;
+            tar_path
+        }
+    };
+    let temp_layer = compress_layer_file(&tar_path, home_dir, CompressType::Tgz)?;
+    if format!("sha256:{}", temp_layer.tar_sha256) != diff_id {
+        fs::remove_file(&temp_layer.compress_layer_path)?;
+        return Err(anyhow!("layer does not match its diff_id: {}", diff_id));
+    }
+    let blobs = &home_dir.cache.blobs;
+    blobs.move_to_blob(
+        &temp_layer.compress_layer_path,
+        &temp_layer.compressed_tar_sha256,
+        &temp_layer.tar_sha256,
+    )?;
+    blobs.create_layer_config(&temp_layer.tar_sha256, &temp_layer.compressed_tar_sha256, CompressType::Tgz)
+}
+
+#[test]
+fn load_docker_archive_works() -> Result<()> {
+    use std::io::{Cursor, Write};
+
+    use tar::{Builder, Header};
+
+    fn append<W: Write>(builder: &mut Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, Cursor::new(data))?;
+        Ok(())
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(&temp_dir.path().join("home"))?;
+    let mut layer_builder = Builder::new(Vec::new());
+    append(&mut layer_builder, "hello.txt", b"hello")?;
+    let layer = layer_builder.into_inner()?;
+    let diff_id = format!("sha256:{}", bytes_sha256(&layer));
+    let config = format!(
+        r#"{{"architecture":"amd64","os":"linux","config":{{}},"rootfs":{{"type":"layers","diff_ids":["{}"]}},"history":[]}}"#,
+        diff_id
+    );
+    let manifest_json = r#"[{"Config":"config.json","RepoTags":["demo:1.0"],"Layers":["layer/layer.tar"]}]"#;
+    let archive_path = temp_dir.path().join("demo.tar");
+    let mut builder = Builder::new(File::create(&archive_path)?);
+    append(&mut builder, "layer/layer.tar", &layer)?;
+    append(&mut builder, "config.json", config.as_bytes())?;
+    append(&mut builder, "manifest.json", manifest_json.as_bytes())?;
+    builder.into_inner()?;
+
+    let pull_result = load_docker_archive(&archive_path, &home_dir)?;
+    assert_eq!(
+        pull_result.manifest.config_digest(),
+        format!("sha256:{}", bytes_sha256(config.as_bytes()))
+    );
+    let layers = pull_result.manifest.layers();
+    assert_eq!(layers.len(), 1);
+    let local_layer = home_dir.cache.blobs.local_layer(&crate::container::RegDigest::new_with_digest(layers[0].digest.to_string()));
+    assert_eq!(format!("sha256:{}", local_layer.expect("layer imported").diff_layer_sha), diff_id);
+    Ok(())
+}
+
+#[test]
+fn load_oci_layout_works() -> Result<()> {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn write_blob(layout_dir: &Path, data: &[u8]) -> Result<String> {
+        let sha256 = bytes_sha256(data);
+        fs::write(layout_dir.join("blobs/sha256").join(&sha256), data)?;
+        Ok(format!("sha256:{}", sha256))
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let home_dir = HomeDir::new_home_dir(&temp_dir.path().join("home"))?;
+    let layout_dir = temp_dir.path().join("layout");
+    fs::create_dir_all(layout_dir.join("blobs/sha256"))?;
+    fs::write(layout_dir.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)?;
+    let layer_tar = b"not a real tar, only the digest matters".to_vec();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&layer_tar)?;
+    let layer = encoder.finish()?;
+    let layer_digest = write_blob(&layout_dir, &layer)?;
+    let config = format!(
+        r#"{{"os":"linux","config":{{}},"rootfs":{{"type":"layers","diff_ids":["sha256:{}"]}},"history":[]}}"#,
+        bytes_sha256(&layer_tar)
+    );
+    let config_digest = write_blob(&layout_dir, config.as_bytes())?;
+    let manifest = format!(
+        r#"{{"schemaVersion":2,"mediaType":"{}","config":{{"mediaType":"{}","size":{},"digest":"{}"}},"layers":[{{"mediaType":"{}","size":{},"digest":"{}"}}]}}"#,
+        RegContentType::OCI_MANIFEST.val(),
+        RegContentType::OCI_IMAGE_CONFIG.val(),
+        config.len(),
+        config_digest,
+        RegContentType::OCI_LAYER_TGZ.val(),
+        layer.len(),
+        layer_digest
+    );
+    let manifest_digest = write_blob(&layout_dir, manifest.as_bytes())?;
+    let index = format!(
+        r#"{{"schemaVersion":2,"manifests":[{{"mediaType":"{}","size":{},"digest":"{}","annotations":{{"org.opencontainers.image.ref.name":"1.0"}}}}]}}"#,
+        RegContentType::OCI_MANIFEST.val(),
+        manifest.len(),
+        manifest_digest
+    );
+    fs::write(layout_dir.join("index.json"), index)?;
+
+    let pull_result = load_oci_layout(&layout_dir, Some("1.0"), None, &home_dir)?;
+    assert_eq!(pull_result.manifest.layers()[0].digest, layer_digest);
+    assert_eq!(pull_result.manifest.config_digest(), config_digest);
+    let local_layer = home_dir.cache.blobs.local_layer(&RegDigest::new_with_digest(layer_digest)).expect("layer imported");
+    assert_eq!(local_layer.diff_layer_sha, bytes_sha256(&layer_tar));
+    assert!(load_oci_layout(&layout_dir, Some("2.0"), None, &home_dir).is_err());
+    Ok(())
+}
@@ -1,5 +1,6 @@
 pub mod build;
 pub mod clean;
+pub mod load;
 pub mod pull;
 pub mod show_info;
 pub mod transform;
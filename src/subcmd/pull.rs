@@ -1,23 +1,39 @@
-use std::collections::HashMap;
-use std::fs::File;
+use anyhow::Result;
+use log::{debug, info};
 
-use anyhow::{anyhow, Result};
-use log::info;
-use sha2::{Digest, Sha256};
-
-use crate::adapter::SourceInfo;
+use crate::adapter::{LocalSource, SourceInfo};
 use crate::config::RegAuthType;
 use crate::container::http::download::DownloadResult;
 use crate::container::image::docker::DockerConfigBlob;
-use crate::container::image::oci::OciConfigBlob;
-use crate::container::manifest::Manifest;
+use crate::container::image::oci::{OciConfigBlob, OciManifest};
+use crate::container::image::Rootfs;
+use crate::container::manifest::{CommonManifestConfig, Manifest};
 use crate::container::proxy::ProxyInfo;
-use crate::container::{ConfigBlobEnum, Layer, Reference, RegContentType, RegDigest, Registry, RegistryCreateInfo};
+use crate::container::{ConfigBlobEnum, Platform, Reference, RegContentType, RegDigest, Registry, RegistryCreateInfo};
 use crate::progress::manager::ProcessorManager;
 use crate::progress::Processor;
-use crate::util::compress::uncompress;
+use crate::subcmd::load::{load_docker_archive, load_oci_layout};
+use crate::util::sha::bytes_sha256;
 use crate::GLOBAL_CONFIG;
 
+/// 获取源镜像，scratch和本地的镜像不需要访问镜像仓库
+pub fn pull_source(
+    source_info: &SourceInfo,
+    source_auth: RegAuthType,
+    use_https: bool,
+    read_timeout_second: u64,
+    proxy: Option<ProxyInfo>,
+) -> Result<PullResult> {
+    match &source_info.local {
+        Some(LocalSource::DockerArchive(path)) => load_docker_archive(path, &GLOBAL_CONFIG.home_dir),
+        Some(LocalSource::OciLayout { path, tag }) => {
+            load_oci_layout(path, tag.as_deref(), source_info.platform.as_ref(), &GLOBAL_CONFIG.home_dir)
+        }
+        None if source_info.image_info.is_scratch() => scratch(source_info.platform.as_ref()),
+        None => pull(source_info, source_auth, use_https, read_timeout_second, proxy),
+    }
+}
+
 pub fn pull(
     source_info: &SourceInfo,
     source_auth: RegAuthType,
@@ -43,36 +59,26 @@ pub fn pull(
     };
     let mut from_registry = Registry::open(use_https, image_host, info)?;
     info!("Get source image manifest info.");
-    let (manifest, manifest_raw) = from_registry.image_manager.manifests(&from_image_reference, source_info.platform.clone())?;
+    let (manifest, _) = from_registry.image_manager.manifests(&from_image_reference, source_info.platform.clone())?;
     info!("Source image type: {}", manifest.manifest_type());
     let config_digest = manifest.config_digest();
     let layers = manifest.layers();
     let mut reg_downloader_vec = Vec::<Box<dyn Processor<DownloadResult>>>::new();
     for layer in &layers {
         let digest = RegDigest::new_with_digest(layer.digest.to_string());
-        let downloader = from_registry.image_manager.layer_blob_download(from_image_reference.image_name, &digest, Some(layer.size))?;
+        let compress_type = RegContentType::compress_type(layer.media_type)?;
+        let downloader =
+            from_registry.image_manager.layer_blob_download(from_image_reference.image_name, &digest, Some(layer.size), compress_type)?;
         reg_downloader_vec.push(Box::new(downloader))
     }
     let manager = ProcessorManager::new_processor_manager(reg_downloader_vec)?;
     info!("Start pulling... (total={})", manager.size());
-    let download_results = manager.wait_all_done()?;
-    let layer_digest_map = layer_to_map(&layers);
-    for download_result in &download_results {
-        if download_result.local_existed {
-            continue;
-        }
-        let manifest_layer = layer_digest_map.get(download_result.blob_config.reg_digest.digest.as_str()).expect("internal error");
-        let layer_compress_type = RegContentType::compress_type(manifest_layer.media_type)?;
-        let digest = RegDigest::new_with_digest(manifest_layer.digest.to_string());
-        let download_path = download_result.file_path.as_ref().ok_or_else(|| anyhow!("can not found download file"))?;
-        // 计算解压完的tar的sha256值
-        let mut download_file = File::open(download_path)?;
-        let mut sha256_encode = Sha256::new();
-        uncompress(layer_compress_type, &mut download_file, &mut sha256_encode)?;
-        let sha256 = &sha256_encode.finalize()[..];
-        let tar_sha256 = hex::encode(sha256);
-        GLOBAL_CONFIG.home_dir.cache.blobs.create_layer_config(&tar_sha256, &digest.sha256, layer_compress_type)?;
-        GLOBAL_CONFIG.home_dir.cache.blobs.move_to_blob(download_path, &digest.sha256, &tar_sha256)?;
+    // layer在下载完成时已经校验并保存到本地缓存中
+    for download_result in manager.wait_all_done()? {
+        debug!(
+            "Download done: {} {:?} (local_existed={})",
+            download_result.blob_config.short_hash, download_result.file_path, download_result.local_existed
+        );
     }
 
     let config_blob_enum = match &manifest {
@@ -89,20 +95,59 @@ pub fn pull(
     Ok(PullResult {
         config_blob: config_blob_enum,
         manifest,
-        manifest_raw,
     })
 }
 
-fn layer_to_map<'a>(layers: &'a [Layer]) -> HashMap<&'a str, &'a Layer<'a>> {
-    let mut map = HashMap::<&str, &Layer>::with_capacity(layers.len());
-    for layer in layers {
-        map.insert(layer.digest, layer);
-    }
-    map
+/// FROM scratch 使用没有layer的空镜像，不需要访问镜像仓库
+pub fn scratch(platform: Option<&Platform>) -> Result<PullResult> {
+    let platform = platform.cloned().unwrap_or_default();
+    info!("Use an empty base image for 'scratch'. platform='{}'", platform);
+    let config_blob = OciConfigBlob {
+        architecture: Some(platform.arch),
+        os: Some(platform.os),
+        rootfs: Rootfs {
+            diff_ids: Vec::new(),
+            type_field: "layers".to_string(),
+        },
+        ..Default::default()
+    };
+    let config_blob_raw = serde_json::to_string(&config_blob)?;
+    let manifest = OciManifest {
+        schema_version: 2,
+        media_type: Some(RegContentType::OCI_MANIFEST.val().to_string()),
+        config: CommonManifestConfig {
+            media_type: RegContentType::OCI_IMAGE_CONFIG.val().to_string(),
+            size: config_blob_raw.len() as u64,
+            digest: format!("sha256:{}", bytes_sha256(config_blob_raw.as_bytes())),
+        },
+        layers: Vec::new(),
+    };
+    Ok(PullResult {
+        config_blob: ConfigBlobEnum::OciV1(config_blob),
+        manifest: Manifest::OciV1(manifest),
+    })
 }
 
 pub struct PullResult {
     pub config_blob: ConfigBlobEnum,
     pub manifest: Manifest,
-    pub manifest_raw: String,
+}
+
+#[test]
+fn scratch_works() -> Result<()> {
+    let platform = Platform {
+        os: "linux".to_string(),
+        arch: "arm64".to_string(),
+        variant: None,
+    };
+    let pull_result = scratch(Some(&platform))?;
+    assert!(pull_result.manifest.layers().is_empty());
+    let ConfigBlobEnum::OciV1(config_blob) = &pull_result.config_blob else {
+        panic!("scratch must be an oci image")
+    };
+    assert_eq!(config_blob.architecture.as_deref(), Some("arm64"));
+    assert!(config_blob.rootfs.diff_ids.is_empty());
+    let config_digest = format!("sha256:{}", bytes_sha256(serde_json::to_string(config_blob)?.as_bytes()));
+    assert_eq!(pull_result.manifest.config_digest(), config_digest);
+    Ok(())
 }
@@ -1,17 +1,15 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use colored::Colorize;
 use log::info;
 use std::path::PathBuf;
 
 use crate::adapter::docker::DockerfileAdapter;
-use crate::adapter::registry::RegistryTargetAdapter;
-use crate::adapter::tar::TarTargetAdapter;
-use crate::adapter::{BuildInfo, SourceInfo};
-use crate::config::cmd::{TargetType, TransformCmdArgs};
+use crate::adapter::{BuildInfo, LocalSource, SourceInfo};
+use crate::config::cmd::{split_layout_tag, TransformCmdArgs};
 use crate::config::RegAuthType;
 use crate::container::proxy::ProxyInfo;
-use crate::subcmd::build::{build_target_config_blob, build_target_manifest};
-use crate::subcmd::pull::pull;
+use crate::subcmd::build::{build_target_config_blob, build_target_manifest, save_targets, upload_chunk_bytes, TargetConnection};
+use crate::subcmd::pull::pull_source;
 use crate::GLOBAL_CONFIG;
 
 pub struct TransformCommand {}
@@ -43,10 +41,7 @@ Transform job successful!
 Target image:
 {}
 "#,
-            match &build_args.target {
-                TargetType::Registry(r) => (*r).clone(),
-                TargetType::Tar(tar_arg) => format!("Path: {}", tar_arg.path),
-            }
+            build_args.target.iter().map(|target| target.to_string()).collect::<Vec<_>>().join("\n")
         )
         .green()
     );
@@ -68,6 +63,28 @@ Transform job failed!
 }
 
 fn gen_source_info(transform_args: &TransformCmdArgs) -> Result<(SourceInfo, BuildInfo, RegAuthType)> {
+    let source_image = &transform_args.source_image;
+    let local_source = if let Some(path) = source_image.strip_prefix("docker-archive:") {
+        Some(LocalSource::DockerArchive(PathBuf::from(path)))
+    } else if let Some(layout) = source_image.strip_prefix("oci:") {
+        let (path, tag) = split_layout_tag(layout);
+        Some(LocalSource::OciLayout {
+            path: PathBuf::from(path),
+            tag,
+        })
+    } else {
+        None
+    };
+    if let Some(local_source) = local_source {
+        let image_info = local_source.image_info();
+        let source_reg_auth = RegAuthType::build_auth(image_info.image_host.clone(), None);
+        let source_info = SourceInfo {
+            image_info,
+            platform: None,
+            local: Some(local_source),
+        };
+        return Ok((source_info, BuildInfo::default(), source_reg_auth));
+    }
     let fake_dockerfile_body = format!("FROM {}", &transform_args.source_image);
     let (mut image_info, build_info) = DockerfileAdapter::parse_from_str(&fake_dockerfile_body)?;
     // add library
@@ -80,6 +97,7 @@ fn gen_source_info(transform_args: &TransformCmdArgs) -> Result<(SourceInfo, Bui
         SourceInfo {
             image_info,
             platform: None,
+            local: None,
         },
         build_info,
         source_reg_auth,
@@ -93,46 +111,34 @@ pub fn transform_handle(
     transform_cmds: &TransformCmdArgs,
     proxy_info: Option<ProxyInfo>,
 ) -> Result<()> {
-    let _home_dir = GLOBAL_CONFIG.home_dir.clone();
-    let pull_result = pull(
+    let home_dir = GLOBAL_CONFIG.home_dir.clone();
+    let pull_result = pull_source(
         &source_info,
         source_auth,
         !transform_cmds.allow_insecure,
         transform_cmds.conn_timeout,
         proxy_info,
     )?;
-    let target_config_blob = build_target_config_blob(build_info, &pull_result.config_blob, None, &transform_cmds.format);
+    let target_config_blob = build_target_config_blob(build_info, &pull_result.config_blob, None, &transform_cmds.format)?;
     let source_manifest = pull_result.manifest;
-    let source_manifest_raw = pull_result.manifest_raw;
     let target_config_blob_serialize = target_config_blob.serialize()?;
     info!("Build a new target manifest.");
     let target_manifest = build_target_manifest(source_manifest, &transform_cmds.format, None, &target_config_blob_serialize)?;
-    match &transform_cmds.target {
-        TargetType::Registry(image) => {
-            let registry_adapter = RegistryTargetAdapter::new(
-                image,
-                transform_cmds.format.clone(),
-                !transform_cmds.target_allow_insecure,
-                target_manifest,
-                target_config_blob_serialize,
-                transform_cmds.target_auth.as_ref(),
-                transform_cmds.conn_timeout,
-                transform_cmds.target_proxy.clone(),
-            )?;
-            registry_adapter.upload()?
-        }
-        TargetType::Tar(tar_arg) => {
-            let image_raw_name = source_info.image_info.image_raw_name.ok_or_else(|| anyhow!("must set a raw name"))?;
-            let adapter = TarTargetAdapter {
-                image_raw_name,
-                target_manifest,
-                manifest_raw: source_manifest_raw,
-                target_config_blob_serialize,
-                save_path: PathBuf::from(tar_arg.path.clone()),
-                use_gzip: tar_arg.usb_gzip,
-            };
-            adapter.save()?;
-        }
-    }
-    Ok(())
+    let connection = TargetConnection {
+        allow_insecure: transform_cmds.target_allow_insecure,
+        auth: transform_cmds.target_auth.as_ref(),
+        proxy: transform_cmds.target_proxy.as_ref(),
+        hosts: &transform_cmds.target_hosts,
+        conn_timeout: transform_cmds.conn_timeout,
+        upload_chunk_size: upload_chunk_bytes(transform_cmds.upload_chunk_size)?,
+        mount_source: source_info.registry_image(),
+    };
+    save_targets(
+        &transform_cmds.target,
+        &transform_cmds.format,
+        &connection,
+        target_manifest,
+        target_config_blob_serialize,
+        &home_dir,
+    )
 }
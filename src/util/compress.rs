@@ -1,10 +1,13 @@
+use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
+use std::path::Path;
 
 use anyhow::Result;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use xz2::read::XzDecoder;
 use zstd::{stream, DEFAULT_COMPRESSION_LEVEL};
 
 use crate::container::CompressType;
@@ -18,6 +21,16 @@ pub fn uncompress<R: Read, W: Write>(compress_type: CompressType, tar_input: &mu
     Ok(())
 }
 
+/// 返回解压后的读取流
+pub fn uncompress_reader<R: Read + 'static>(compress_type: CompressType, input: R) -> Result<Box<dyn Read>> {
+    let reader: Box<dyn Read> = match compress_type {
+        CompressType::Tar => Box::new(input),
+        CompressType::Tgz => Box::new(GzDecoder::new(input)),
+        CompressType::Zstd => Box::new(stream::Decoder::new(input)?),
+    };
+    Ok(reader)
+}
+
 pub fn compress<R: Read, W: ?Sized + Write>(compress_type: CompressType, tar_input_reader: &mut R, output_writer: &mut W) -> Result<()> {
     match compress_type {
         CompressType::Tar => io::copy(tar_input_reader, output_writer).map(|_| ())?,
@@ -47,3 +60,31 @@ pub fn compress_gz<R: Read, W: ?Sized + Write>(tar_input_reader: &mut R, output_
     let _ = io::copy(tar_input_reader, &mut encoder)?;
     Ok(())
 }
+
+/// 根据文件头判断压缩类型，无法识别时为tar
+pub fn detect_compress_type(file_path: &Path) -> Result<CompressType> {
+    let mut magic = [0u8; 4];
+    let read_size = File::open(file_path)?.read(&mut magic)?;
+    Ok(match &magic[..read_size] {
+        [0x1f, 0x8b, ..] => CompressType::Tgz,
+        [0x28, 0xb5, 0x2f, 0xfd] => CompressType::Zstd,
+        _ => CompressType::Tar,
+    })
+}
+
+/// 根据文件名判断是否为可以自动解压的tar归档，是则返回解压后的tar流，否则返回None
+pub fn archive_reader(file_path: &Path) -> Result<Option<Box<dyn Read>>> {
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let reader: Box<dyn Read> = if file_name.ends_with(".tar") {
+        Box::new(File::open(file_path)?)
+    } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        Box::new(GzDecoder::new(File::open(file_path)?))
+    } else if file_name.ends_with(".tar.zst") {
+        Box::new(stream::Decoder::new(File::open(file_path)?)?)
+    } else if file_name.ends_with(".tar.xz") {
+        Box::new(XzDecoder::new(File::open(file_path)?))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(reader))
+}
@@ -0,0 +1,97 @@
+use std::fs::read_to_string;
+use std::path::{Component, Path};
+
+use anyhow::Result;
+
+use crate::util::glob::match_path;
+
+/// 构建上下文中的 .dockerignore，后面的规则优先，'!' 开头的规则重新包含文件
+#[derive(Default)]
+pub struct DockerIgnore {
+    rules: Vec<IgnoreRule>,
+}
+
+struct IgnoreRule {
+    pattern: String,
+    exclude: bool,
+}
+
+impl DockerIgnore {
+    /// 读取构建上下文中的 .dockerignore，文件不存在时不忽略任何文件
+    pub fn load(context_dir: &Path) -> Result<DockerIgnore> {
+        let ignore_file = context_dir.join(".dockerignore");
+        if !ignore_file.is_file() {
+            return Ok(DockerIgnore::default());
+        }
+        Ok(DockerIgnore::parse(&read_to_string(ignore_file)?))
+    }
+
+    pub fn parse(content: &str) -> DockerIgnore {
+        let rules = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (exclude, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (false, pattern.trim()),
+                    None => (true, line),
+                };
+                let pattern = clean_path(Path::new(pattern));
+                (!pattern.is_empty()).then_some(IgnoreRule { pattern, exclude })
+            })
+            .collect();
+        DockerIgnore { rules }
+    }
+
+    /// 判断构建上下文中的相对路径是否被忽略，目录被忽略时其中的文件也被忽略
+    pub fn is_ignored(&self, relative_path: &Path) -> bool {
+        let path = clean_path(relative_path);
+        if path.is_empty() {
+            return false;
+        }
+        let mut ignored = false;
+        for rule in &self.rules {
+            let matched = Path::new(&path)
+                .ancestors()
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .any(|ancestor| match_path(&rule.pattern, &ancestor.to_string_lossy()));
+            if matched {
+                ignored = rule.exclude;
+            }
+        }
+        ignored
+    }
+
+    /// 有 '!' 规则时被忽略的目录中也可能有需要包含的文件
+    pub fn has_exceptions(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.exclude)
+    }
+}
+
+/// 去掉路径中的根目录和 '.'，处理 '..'，使用 '/' 分隔
+fn clean_path(path: &Path) -> String {
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => segments.push(name.to_string_lossy().to_string()),
+            Component::ParentDir => {
+                segments.pop();
+            }
+            _ => {}
+        }
+    }
+    segments.join("/")
+}
+
+#[test]
+fn dockerignore_works() {
+    let docker_ignore = DockerIgnore::parse("# comment\n/target\n**/*.log\n!keep.log\ndocs\n!docs/README.md\n");
+    assert!(docker_ignore.is_ignored(Path::new("target")));
+    assert!(docker_ignore.is_ignored(Path::new("target/debug/app")));
+    assert!(docker_ignore.is_ignored(Path::new("logs/a/error.log")));
+    assert!(!docker_ignore.is_ignored(Path::new("keep.log")));
+    assert!(docker_ignore.is_ignored(Path::new("docs/guide.md")));
+    assert!(!docker_ignore.is_ignored(Path::new("docs/README.md")));
+    assert!(!docker_ignore.is_ignored(Path::new("src/main.rs")));
+    assert!(docker_ignore.has_exceptions());
+}
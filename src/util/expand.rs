@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+
+/// 按照Dockerfile的规则展开字符串中的变量。
+/// 支持 '$VAR'、'${VAR}'、'${VAR:-default}'、'${VAR:+alt}'，'\$' 表示普通的 '$'
+pub fn expand_vars<F: Fn(&str) -> Option<String>>(input: &str, lookup: &F) -> Result<String> {
+    expand(input, lookup, false)
+}
+
+/// 只展开 lookup 中存在的变量，其余的引用和 '\$' 原样保留，之后可以再用 expand_vars 展开。
+/// lookup 返回的值需要是同样的格式，普通字符串使用 escape_vars 转义
+pub fn expand_vars_partial<F: Fn(&str) -> Option<String>>(input: &str, lookup: &F) -> Result<String> {
+    expand(input, lookup, true)
+}
+
+/// 转义字符串中的 '$'，展开时作为普通字符
+pub fn escape_vars(value: &str) -> String {
+    value.replace('$', "\\$")
+}
+
+fn expand<F: Fn(&str) -> Option<String>>(input: &str, lookup: &F, keep_unresolved: bool) -> Result<String> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let mut output = String::with_capacity(input.len());
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if c == '\\' && chars.get(index + 1) == Some(&'$') {
+            output.push_str(if keep_unresolved { "\\$" } else { "$" });
+            index += 2;
+            continue;
+        }
+        if c != '$' {
+            output.push(c);
+            index += 1;
+            continue;
+        }
+        match chars.get(index + 1) {
+            Some('{') => {
+                let start = index + 2;
+                let mut end = start;
+                let mut depth = 1;
+                while end < chars.len() {
+                    match chars[end] {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    end += 1;
+                }
+                if end >= chars.len() {
+                    return Err(anyhow!("missing '}}' in '{}'", input));
+                }
+                let expr = chars[start..end].iter().collect::<String>();
+                output.push_str(&expand_braced(&expr, lookup, keep_unresolved)?);
+                index = end + 1;
+            }
+            Some(next) if is_name_char(*next) => {
+                let start = index + 1;
+                let mut end = start;
+                while end < chars.len() && is_name_char(chars[end]) {
+                    end += 1;
+                }
+                let name = chars[start..end].iter().collect::<String>();
+                match lookup(&name) {
+                    Some(value) => output.push_str(&value),
+                    None if keep_unresolved => output.push_str(&format!("${}", name)),
+                    None => {}
+                }
+                index = end;
+            }
+            _ => {
+                output.push('$');
+                index += 1;
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// 展开 '${...}' 中的内容
+fn expand_braced<F: Fn(&str) -> Option<String>>(expr: &str, lookup: &F, keep_unresolved: bool) -> Result<String> {
+    let name_len = expr.find(|c: char| !is_name_char(c)).unwrap_or(expr.len());
+    let (name, modifier) = expr.split_at(name_len);
+    if name.is_empty() {
+        return Err(anyhow!("bad substitution: ${{{}}}", expr));
+    }
+    let value = lookup(name);
+    if value.is_none() && keep_unresolved {
+        return Ok(format!("${{{}}}", expr));
+    }
+    if modifier.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+    // 带 ':' 时空字符串等同于未设置
+    let (check_empty, modifier) = match modifier.strip_prefix(':') {
+        Some(modifier) => (true, modifier),
+        None => (false, modifier),
+    };
+    let is_set = value.as_ref().is_some_and(|value| !check_empty || !value.is_empty());
+    if let Some(word) = modifier.strip_prefix('-') {
+        if is_set {
+            Ok(value.unwrap_or_default())
+        } else {
+            expand(word, lookup, keep_unresolved)
+        }
+    } else if let Some(word) = modifier.strip_prefix('+') {
+        if is_set {
+            expand(word, lookup, keep_unresolved)
+        } else {
+            Ok(String::new())
+        }
+    } else {
+        Err(anyhow!("unsupported modifier in ${{{}}}", expr))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[test]
+fn expand_works() -> Result<()> {
+    let lookup = |name: &str| match name {
+        "VERSION" => Some("1.2".to_string()),
+        "EMPTY" => Some(String::new()),
+        _ => None,
+    };
+    assert_eq!(expand_vars("app:$VERSION", &lookup)?, "app:1.2");
+    assert_eq!(expand_vars("app-${VERSION}.jar", &lookup)?, "app-1.2.jar");
+    assert_eq!(expand_vars("${MISSING:-${VERSION}}", &lookup)?, "1.2");
+    assert_eq!(expand_vars("${EMPTY:-default}|${EMPTY-default}", &lookup)?, "default|");
+    assert_eq!(expand_vars("${VERSION:+v$VERSION}|${MISSING:+alt}", &lookup)?, "v1.2|");
+    assert_eq!(expand_vars("\\$VERSION costs $5 $", &lookup)?, "$VERSION costs  $");
+    assert!(expand_vars("${VERSION", &lookup).is_err());
+    Ok(())
+}
+
+#[test]
+fn expand_partial_works() -> Result<()> {
+    let lookup = |name: &str| match name {
+        "APP" => Some(escape_vars("/opt/$app")),
+        _ => None,
+    };
+    let partial = expand_vars_partial("$APP/bin:$PATH:${HOME:-/root}:\\$LITERAL", &lookup)?;
+    assert_eq!(partial, "/opt/\\$app/bin:$PATH:${HOME:-/root}:\\$LITERAL");
+    let base = |name: &str| match name {
+        "PATH" => Some("/usr/bin".to_string()),
+        _ => None,
+    };
+    assert_eq!(expand_vars(&partial, &base)?, "/opt/$app/bin:/usr/bin:/root:$LITERAL");
+    Ok(())
+}
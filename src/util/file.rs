@@ -1,6 +1,9 @@
+use std::fs::File;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use crate::util::random;
 
 /// 删除文件/目录，当文件不存在时依然返回成功
 pub fn remove(path: &Path) -> Result<()> {
@@ -15,6 +18,50 @@ pub fn remove(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// 先写入同目录下的临时文件再重命名，避免其他进程读到写了一半的文件
+pub fn write_atomic<F: FnOnce(&mut File) -> Result<()>>(path: &Path, write: F) -> Result<()> {
+    let file_name = path.file_name().ok_or_else(|| anyhow!("error file path: {:?}", path))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), random::random_str(8)));
+    let result = File::create(&temp_path).map_err(anyhow::Error::from).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()?;
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(std::fs::rename(&temp_path, path)?),
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+/// 将容器中的路径转换为绝对路径，相对路径基于 work_dir，并处理 '.' 和 '..'。
+/// 以 '/' 或 '.' 结尾的路径表示目录，返回值会以 '/' 结尾
+pub fn container_abs_path(work_dir: &str, path: &str) -> String {
+    let is_dir = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..") || path == "." || path == "..";
+    let full_path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", work_dir, path)
+    };
+    let mut segments = Vec::new();
+    for segment in full_path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut abs_path = format!("/{}", segments.join("/"));
+    if is_dir && !abs_path.ends_with('/') {
+        abs_path.push('/');
+    }
+    abs_path
+}
+
 /// Path的扩展，删除文件或者目录
 pub trait PathExt {
     fn clean_path(&self) -> Result<()>;
@@ -31,3 +78,12 @@ impl PathExt for PathBuf {
         remove(self)
     }
 }
+
+#[test]
+fn container_abs_path_works() {
+    assert_eq!(container_abs_path("/app", "conf/a.yml"), "/app/conf/a.yml");
+    assert_eq!(container_abs_path("/app", "."), "/app/");
+    assert_eq!(container_abs_path("/app/", "../lib/"), "/lib/");
+    assert_eq!(container_abs_path("/app", "/etc/b.conf"), "/etc/b.conf");
+    assert_eq!(container_abs_path("/", "/"), "/");
+}
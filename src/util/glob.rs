@@ -0,0 +1,79 @@
+/// 判断路径是否匹配glob模式，路径和模式都使用 '/' 分隔。
+/// 支持 '*'、'?'、'[...]'，'**' 可以匹配任意层目录
+pub fn match_path(pattern: &str, path: &str) -> bool {
+    let pattern_segments = pattern.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<&str>>();
+    let path_segments = path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<&str>>();
+    match_segments(&pattern_segments, &path_segments)
+}
+
+/// 判断字符串中是否包含glob的特殊字符
+pub fn has_magic(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => !path.is_empty() && match_segment(first, path[0]) && match_segments(rest, &path[1..]),
+    }
+}
+
+/// 匹配单个文件名
+pub fn match_segment(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+    match_chars(&pattern, &name)
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_chars(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_chars(&pattern[1..], &name[1..]),
+        Some('\\') if pattern.len() > 1 => name.first() == Some(&pattern[1]) && match_chars(&pattern[2..], &name[1..]),
+        // ']' 紧跟在 '[' 之后时作为普通字符
+        Some('[') => match pattern.iter().skip(2).position(|c| *c == ']') {
+            Some(position) => {
+                let class_end = position + 2;
+                !name.is_empty() && match_class(&pattern[1..class_end], name[0]) && match_chars(&pattern[class_end + 1..], &name[1..])
+            }
+            None => name.first() == Some(&'[') && match_chars(&pattern[1..], &name[1..]),
+        },
+        Some(c) => name.first() == Some(c) && match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// 匹配 '[...]' 中的字符集合，支持 'a-z' 范围和 '!'、'^' 取反
+fn match_class(class: &[char], c: char) -> bool {
+    let (negate, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            matched |= class[index] <= c && c <= class[index + 2];
+            index += 3;
+        } else {
+            matched |= class[index] == c;
+            index += 1;
+        }
+    }
+    matched != negate
+}
+
+#[test]
+fn glob_works() {
+    assert!(match_path("*.go", "main.go"));
+    assert!(!match_path("*.go", "cmd/main.go"));
+    assert!(match_path("**/*.go", "cmd/app/main.go"));
+    assert!(match_path("**/*.go", "main.go"));
+    assert!(match_path("docs/**", "docs/a/b.md"));
+    assert!(match_path("file?.[a-c]", "file1.b"));
+    assert!(!match_path("file[!0-9].txt", "file1.txt"));
+    assert!(match_path("\\*.txt", "*.txt"));
+    assert!(has_magic("src/*.rs"));
+    assert!(!has_magic("src/main.rs"));
+}
@@ -1,4 +1,7 @@
 pub mod compress;
+pub mod dockerignore;
+pub mod expand;
 pub mod file;
+pub mod glob;
 pub mod random;
 pub mod sha;